@@ -0,0 +1,99 @@
+//! Times merging generated bins at a few buffer sizes, and parsing a cue sheet with many tracks.
+//!
+//! Run with `cargo bench --features bench -- [bins] [MiB per bin]`, 4 bins of 64 MiB by default. The bins are
+//! written to a directory under the system temp directory, which is removed afterwards.
+
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, Instant};
+
+use binmerge_rs::{merge_files, parse_cue};
+
+const SECTOR_SIZE: usize = 2352;
+
+// Fastest of a few runs, which is less noisy than the mean when the disk cache is warm
+fn fastest<E>(runs: u32, mut run: impl FnMut() -> Result<(), E>) -> Result<Duration, E> {
+    let mut fastest = Duration::MAX;
+    for _ in 0..runs {
+        let start = Instant::now();
+        run()?;
+        fastest = fastest.min(start.elapsed());
+    }
+    Ok(fastest)
+}
+
+fn generate_bins(dir: &Path, count: usize, sectors: usize) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut bins = Vec::new();
+    for i in 0..count {
+        let bin = dir.join(format!("bench (Track {:02}).bin", i + 1));
+        fs::write(&bin, vec![i as u8; sectors * SECTOR_SIZE])?;
+        bins.push(bin);
+    }
+    Ok(bins)
+}
+
+// The number of bins and MiB per bin, cargo bench's own flags left out
+fn parse_args() -> Result<(usize, usize), String> {
+    let mut args = env::args().skip(1).filter(|arg| !arg.starts_with("--"));
+    let mut number = |name: &str, default: usize| match args.next() {
+        None => Ok(default),
+        Some(arg) => match arg.parse::<usize>() {
+            Ok(0) | Err(_) => Err(format!("the {} must be a whole number greater than zero, got {}", name, arg)),
+            Ok(n) => Ok(n),
+        },
+    };
+    let count = number("number of bins", 4)?;
+    let mib = number("MiB per bin", 64)?;
+    let sectors = mib.checked_mul(1024 * 1024).ok_or(format!("{} MiB per bin is too large", mib))? / SECTOR_SIZE;
+    Ok((count, sectors))
+}
+
+fn bench(dir: &Path, count: usize, sectors: usize) -> Result<(), Box<dyn Error>> {
+    let bins = generate_bins(dir, count, sectors)?;
+    let merged = dir.join("bench.bin");
+    let total_mib = (count * sectors * SECTOR_SIZE) as f64 / (1024.0 * 1024.0);
+
+    println!("Merging {} bins, {:.0} MiB in total", count, total_mib);
+    for buffer_size in [4 * 1024, 64 * 1024, 1024 * 1024, 8 * 1024 * 1024] {
+        let files: Vec<&Path> = bins.iter().map(PathBuf::as_path).collect();
+        let elapsed = fastest(3, || merge_files(&merged, files.clone(), true, buffer_size).map(|_| ()))?;
+        println!(
+            "  {:>5} KiB buffers: {:>8.1?} ({:.0} MiB/s)",
+            buffer_size / 1024,
+            elapsed,
+            total_mib / elapsed.as_secs_f64()
+        );
+    }
+
+    // Past 99 tracks isn't a real disc, but it makes the parse long enough to time
+    let mut cuesheet = String::new();
+    for i in 0..1000 {
+        let bin = &bins[i % bins.len()];
+        cuesheet += &format!("FILE \"{}\" BINARY\n", bin.file_name().unwrap_or_default().to_string_lossy());
+        cuesheet += &format!("  TRACK {:02} AUDIO\n    INDEX 00 00:00:00\n    INDEX 01 00:02:00\n", i % 99 + 1);
+    }
+    let elapsed = fastest(5, || parse_cue(Cursor::new(&cuesheet), dir).map(|_| ()))?;
+    println!("Parsing a cue sheet of 1000 files: {:.1?}", elapsed);
+    Ok(())
+}
+
+fn run() -> Result<(), Box<dyn Error>> {
+    let (count, sectors) = parse_args()?;
+
+    let dir = env::temp_dir().join(format!("binmerge-bench-{}", process::id()));
+    fs::create_dir_all(&dir)?;
+    let result = bench(&dir, count, sectors);
+    fs::remove_dir_all(&dir)?;
+    result
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,156 @@
+use std::fmt;
+use std::str::FromStr;
+
+use md5::Md5;
+use sha1::{Digest, Sha1};
+
+/// Digest algorithm used to checksum the input and merged bins.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Checksum {
+    Md5,
+    Sha1,
+    /// The zlib CRC32 listed in redump DATs
+    Crc32,
+}
+
+impl Checksum {
+    /// Extension of the sidecar file listing digests of this kind, e.g. `md5`.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Checksum::Md5 => "md5",
+            Checksum::Sha1 => "sha1",
+            Checksum::Crc32 => "sfv",
+        }
+    }
+}
+
+impl fmt::Display for Checksum {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Checksum::Md5 => write!(f, "md5"),
+            Checksum::Sha1 => write!(f, "sha1"),
+            Checksum::Crc32 => write!(f, "crc32"),
+        }
+    }
+}
+
+impl FromStr for Checksum {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "md5" => Ok(Checksum::Md5),
+            "sha1" => Ok(Checksum::Sha1),
+            "crc32" => Ok(Checksum::Crc32),
+            _ => Err(format!("unknown checksum {}, expected md5, sha1 or crc32", s)),
+        }
+    }
+}
+
+/// Streaming digest of a [`Checksum`] kind, fed chunk by chunk as the bins are copied.
+pub enum Hasher {
+    Md5(Md5),
+    Sha1(Sha1),
+    Crc32(u32),
+}
+
+impl Hasher {
+    /// Starts an empty digest.
+    pub fn new(checksum: Checksum) -> Hasher {
+        match checksum {
+            Checksum::Md5 => Hasher::Md5(Md5::new()),
+            Checksum::Sha1 => Hasher::Sha1(Sha1::new()),
+            Checksum::Crc32 => Hasher::Crc32(0),
+        }
+    }
+
+    /// Adds `data` to the digest.
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Md5(hasher) => hasher.update(data),
+            Hasher::Sha1(hasher) => hasher.update(data),
+            Hasher::Crc32(crc) => *crc = crc32_update(*crc, data),
+        }
+    }
+
+    /// Finishes the digest as a lowercase hex string, or uppercase for a CRC32 as redump lists them.
+    pub fn finalize(self) -> String {
+        let digest = match self {
+            Hasher::Md5(hasher) => hasher.finalize().to_vec(),
+            Hasher::Sha1(hasher) => hasher.finalize().to_vec(),
+            Hasher::Crc32(crc) => return format!("{:08X}", crc),
+        };
+        digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+}
+
+// Lookup table for the reflected zlib polynomial, one entry per byte value
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+// Continues the CRC32 `crc` of the data so far with `data`, starting from 0 for no data
+fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    let crc = data.iter().fold(!crc, |crc, &byte| CRC32_TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8));
+    !crc
+}
+
+/// Digests computed while merging, in the same order as the input files.
+pub struct Checksums {
+    /// Digest of each input bin
+    pub inputs: Vec<String>,
+    /// Digest of the merged bin
+    pub merged: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn digest(checksum: Checksum, chunks: &[&[u8]]) -> String {
+        let mut hasher = Hasher::new(checksum);
+        for chunk in chunks {
+            hasher.update(chunk);
+        }
+        hasher.finalize()
+    }
+
+    #[test]
+    fn crc32_of_the_check_string() {
+        assert_eq!(crc32_update(0, b"123456789"), 0xCBF4_3926);
+        assert_eq!(digest(Checksum::Crc32, &[b"123456789"]), "CBF43926");
+    }
+
+    #[test]
+    fn digests_of_no_data() {
+        assert_eq!(crc32_update(0, b""), 0);
+        assert_eq!(digest(Checksum::Crc32, &[]), "00000000");
+        assert_eq!(digest(Checksum::Md5, &[]), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(digest(Checksum::Sha1, &[]), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+    }
+
+    // The bins are hashed a buffer at a time, wherever the buffers happen to end
+    #[test]
+    fn chunked_digests_match_single_shot_ones() {
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i * 7 % 256) as u8).collect();
+        for checksum in [Checksum::Crc32, Checksum::Md5, Checksum::Sha1] {
+            let single_shot = digest(checksum, &[&data]);
+            let chunks: Vec<&[u8]> = data.chunks(333).collect();
+            assert_eq!(digest(checksum, &chunks), single_shot, "{}", checksum);
+            assert_eq!(digest(checksum, &[&data[..1], &[], &data[1..]]), single_shot, "{}", checksum);
+        }
+        assert_eq!(crc32_update(crc32_update(0, &data[..4321]), &data[4321..]), crc32_update(0, &data));
+    }
+}
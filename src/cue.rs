@@ -0,0 +1,1754 @@
+use std::error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead};
+use std::path::{self, Path, PathBuf};
+use std::time::{Duration, Instant};
+use lazy_static::lazy_static;
+use log::{debug, warn};
+
+use rcue::cue::Cue;
+use rcue::errors::CueError;
+use rcue::parser::parse;
+
+use regex::Regex;
+
+use crate::error::BinmergeError;
+
+lazy_static! {
+    // Filenames are usually double quoted, but some rippers write them single quoted or bare. Some tools also write
+    // keywords in lowercase, so they're matched in any case and uppercased when parsed
+    static ref FILE_PATTERN: Regex = Regex::new(r#"(?i)^\s*FILE\s+(?:"(.*)"|'(.*)'|(\S+))\s+(\S+)\s*$"#).unwrap();
+    static ref TRACK_PATTERN: Regex = Regex::new(r#"(?i)^\s*TRACK\s+(\S+)\s+(\S+)"#).unwrap();
+    static ref INDEX_PATTERN: Regex = Regex::new(r#"(?i)^\s*INDEX\s+(\S+)\s+(\S+)"#).unwrap();
+    static ref FLAGS_PATTERN: Regex = Regex::new(r#"(?i)^\s*FLAGS\s+(.*?)\s*$"#).unwrap();
+    static ref GAP_PATTERN: Regex = Regex::new(r#"(?i)^\s*(PREGAP|POSTGAP)\s+(\S+)"#).unwrap();
+    static ref CUESTAMP_PATTERN: Regex = Regex::new(r"^(\d+):(\d+):(\d+)$").unwrap();
+}
+
+// Raw CD sector, only used for bins without any track to take a sector size from
+const SECTOR_SIZE: u32 = 2352;
+
+/// An `INDEX` entry of a track.
+pub struct Index {
+    /// Index number, 0 for the pregap and 1 for the start of the track
+    pub id: u32,
+    /// Timestamp as written in the cue sheet
+    pub stamp: String,
+    /// Offset of the index within its bin, in sectors
+    pub file_offset: u32,
+}
+
+impl Index {
+    /// Creates an index at `file_offset` sectors into its bin.
+    pub fn new(id: u32, stamp: String, file_offset: u32) -> Index {
+        Index {
+            id,
+            stamp,
+            file_offset,
+        }
+    }
+}
+
+impl fmt::Display for Index {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "-- Index --")?;
+        write!(f, "\nIndex id: {}", self.id)?;
+        write!(f, "\nIndex stamp: {}", self.stamp)?;
+        write!(f, "\nIndex file offset: {}", self.file_offset)
+    }
+}
+
+/// A `TRACK` entry of a bin file.
+pub struct Track {
+    /// Track number, which can go past the 99 tracks of a standard CD
+    pub num: u32,
+    /// Indexes of the track, in cue sheet order
+    pub indexes: Vec<Index>,
+    /// Track type as written in the cue sheet, e.g. `MODE2/2352`
+    pub track_type: String,
+    /// Mode part of the track type, e.g. `MODE2` for `MODE2/2352` or `AUDIO`
+    pub mode: String,
+    /// Bytes per sector for the track type
+    pub sector_size: u32,
+    /// Length of the track in sectors, once computed
+    pub sectors: Option<u32>,
+    /// Offset of the track's data within its bin in sectors, from `INDEX 00` if it has a pregap else `INDEX 01`
+    pub file_offset: Option<u32>,
+    /// Sub-code flags of the track (`DCP`, `4CH`, `PRE`, `SCMS`), in cue sheet order
+    pub flags: Vec<String>,
+    /// Other commands of the track (`TITLE`, `PERFORMER`, `REM`...), kept verbatim
+    pub metadata: Vec<String>,
+    /// Length of the `PREGAP` command, which is silence not stored in the bin
+    pub pregap: Option<String>,
+    /// Length of the `POSTGAP` command, which is silence not stored in the bin
+    pub postgap: Option<String>,
+}
+
+impl Track {
+    /// Creates a track without any indexes.
+    pub fn new(num: u32, track_type: String, sector_size: u32) -> Track {
+        Track {
+            num,
+            indexes: Vec::new(),
+            mode: split_track_type(&track_type).0.to_string(),
+            track_type,
+            sector_size,
+            sectors: None,
+            file_offset: None,
+            flags: Vec::new(),
+            metadata: Vec::new(),
+            pregap: None,
+            postgap: None,
+        }
+    }
+
+    /// The indexes of the track, in cue sheet order.
+    pub fn iter_indexes(&self) -> impl Iterator<Item = &Index> {
+        self.indexes.iter()
+    }
+
+    /// The index numbered `id`, e.g. 0 for the pregap or 1 for the start of the track.
+    pub fn index(&self, id: u32) -> Option<&Index> {
+        self.indexes.iter().find(|index| index.id == id)
+    }
+
+    /// Start and end of the track within its bin in bytes. The end is unknown until the track length is computed.
+    pub fn byte_range(&self, sector_size: u32) -> (u64, Option<u64>) {
+        let start = self.file_offset.unwrap_or(0) as u64 * sector_size as u64;
+        let end = self.sectors.map(|sectors| start + sectors as u64 * sector_size as u64);
+        (start, end)
+    }
+}
+
+impl fmt::Display for Track {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "-- Track --")?;
+        write!(f, "\nTrack number: {}", self.num)?;
+        write!(f, "\nTrack type: {}", self.track_type)?;
+        write!(f, "\nTrack mode: {}", self.mode)?;
+        write!(f, "\nTrack file offset: {}", self.file_offset.unwrap_or(0))?;
+        write!(f, "\nTrack start (INDEX 01): {}", self.index(1).map_or(0, |index| index.file_offset))?;
+        write!(f, "\nTrack sectors: {}", self.sectors.unwrap_or(0))?;
+        write!(f, "\nTrack flags: {}", self.flags.join(" "))?;
+        write!(f, "\nTrack pregap: {}", self.pregap.as_deref().unwrap_or("none"))?;
+        write!(f, "\nTrack postgap: {}", self.postgap.as_deref().unwrap_or("none"))?;
+        write!(f, "\nTrack indexes: {}", self.indexes.len())?;
+        for index in self.iter_indexes() {
+            write!(f, "\n{}", index)?;
+        }
+        Ok(())
+    }
+}
+
+// The first of the previous indexes of a track whose stamp isn't in index number order with that of index
+fn out_of_order_index<'a>(previous_indexes: &'a [Index], index: &Index) -> Option<&'a Index> {
+    previous_indexes.iter().find(|previous| {
+        (previous.id < index.id && previous.file_offset > index.file_offset)
+            || (previous.id > index.id && previous.file_offset < index.file_offset)
+    })
+}
+
+// Whole sectors in bytes. Sector counts are u32, so anything past ~10 TB saturates rather than wrapping, and is
+// refused once it has to be written to a cue sheet
+fn sector_count(bytes: u64, sector_size: u32) -> u32 {
+    u32::try_from(bytes / sector_size as u64).unwrap_or(u32::MAX)
+}
+
+/// A `FILE` entry of a cue sheet and the tracks stored in it.
+pub struct BinFile {
+    /// Path of the bin, resolved against the cue sheet's directory
+    pub filename: PathBuf,
+    /// Path of the bin as written in the cue sheet's `FILE` line, backslashes and all
+    pub cue_filename: String,
+    /// File type as written in the cue sheet, e.g. `BINARY` or `WAVE`
+    pub format: String,
+    /// Tracks stored in the bin, in cue sheet order
+    pub tracks: Vec<Track>,
+    /// Size of the bin in bytes
+    pub size: Option<u64>,
+    /// Commands outside of any track, kept verbatim. Disc-level commands (`CATALOG`, `TITLE`, `REM`...) before
+    /// the first `FILE` end up on the first bin.
+    pub metadata: Vec<String>,
+}
+
+impl BinFile {
+    /// Creates a bin file without any tracks, reading its size from disk.
+    pub fn new(filepath: PathBuf, format: String) -> io::Result<BinFile> {
+        let size = fs::metadata(&filepath)?.len(); // Performance hit
+
+        Ok(BinFile::with_size(filepath, format, Some(size)))
+    }
+
+    fn with_size(filepath: PathBuf, format: String, size: Option<u64>) -> BinFile {
+        BinFile {
+            cue_filename: filepath.to_string_lossy().into_owned(),
+            filename: filepath,
+            format,
+            tracks: Vec::new(),
+            size,
+            metadata: Vec::new(),
+        }
+    }
+
+    /// Bytes of zeros needed to round the bin up to a whole number of sectors of its last track.
+    pub fn sector_padding(&self) -> u64 {
+        let sector_size = self.tracks.last().map_or(SECTOR_SIZE, |track| track.sector_size) as u64;
+        let partial = self.size.unwrap_or(0) % sector_size;
+        if partial == 0 { 0 } else { sector_size - partial }
+    }
+
+    /// Rounds the size of the bin up to a whole number of sectors, as if [`BinFile::sector_padding`] zero bytes were
+    /// appended, and returns the bytes added. The last track grows to cover the padded sector.
+    pub fn pad_to_sector(&mut self) -> u64 {
+        let padding = self.sector_padding();
+        self.size = Some(self.size.unwrap_or(0) + padding);
+        self.compute_track_ranges();
+        padding
+    }
+
+    /// Stores the `PREGAP` of the bin's first track in the bin, as if that many sectors of zeros were inserted at its
+    /// start, and returns the bytes added. The track then starts at an `INDEX 00` at the start of the bin, taking in
+    /// the `INDEX 00` pregap it already had.
+    ///
+    /// Fails when another track of the bin has a `PREGAP`, which would have to go in the middle of the bin.
+    pub fn embed_pregap(&mut self) -> Result<u64, CueParseError> {
+        if let Some(track) = self.tracks.iter().skip(1).find(|track| track.pregap.is_some()) {
+            return Err(CueParseError::Unmergeable(format!(
+                "track {:02} has a PREGAP after the start of {}, which can't be stored in the bin",
+                track.num,
+                self.filename.display()
+            )));
+        }
+        let sectors = match self.tracks.first().and_then(|track| track.pregap.as_deref()) {
+            Some(pregap) => stamp_sectors(pregap, false).map_err(|reason| {
+                CueParseError::Unmergeable(format!("PREGAP {} of {}: {}", pregap, self.filename.display(), reason))
+            })?,
+            None => return Ok(0),
+        };
+        self.shift_indexes(sectors as i64)?;
+
+        let track = &mut self.tracks[0];
+        track.pregap = None;
+        match track.indexes.iter_mut().find(|index| index.id == 0) {
+            Some(index) => *index = Index::new(0, sectors_to_cuestamp(0), 0),
+            None => track.indexes.insert(0, Index::new(0, sectors_to_cuestamp(0), 0)),
+        }
+        let bytes = sectors as u64 * track.sector_size as u64;
+        self.size = Some(self.size.unwrap_or(0) + bytes);
+        self.compute_track_ranges();
+        Ok(bytes)
+    }
+
+    /// Leaves the `INDEX 00` pregap of the bin's first track out of the bin, along with anything before it, and
+    /// returns the bytes to cut from the start of the bin. The pregap becomes part of the track's `PREGAP`, so it
+    /// still comes before the track on the disc, as silence.
+    ///
+    /// Fails when another track of the bin has an `INDEX 00` pregap, which would have to be cut out of the middle of
+    /// the bin.
+    pub fn strip_pregap(&mut self) -> Result<u64, CueParseError> {
+        let has_pregap = |track: &Track| match (track.index(0), track.index(1)) {
+            (Some(pregap), Some(start)) => pregap.file_offset < start.file_offset,
+            _ => false,
+        };
+        if let Some(track) = self.tracks.iter().skip(1).find(|track| has_pregap(track)) {
+            return Err(CueParseError::Unmergeable(format!(
+                "track {:02} has an INDEX 00 pregap after the start of {}, which can't be cut out",
+                track.num,
+                self.filename.display()
+            )));
+        }
+        let track = match self.tracks.first() {
+            Some(track) if has_pregap(track) => track,
+            _ => return Ok(0),
+        };
+        let pregap = track.index(0).map_or(0, |index| index.file_offset);
+        let start = track.index(1).map_or(0, |index| index.file_offset);
+        let existing = track.pregap.as_deref().and_then(|pregap| stamp_sectors(pregap, false).ok()).unwrap_or(0);
+        let bytes = start as u64 * track.sector_size as u64;
+        self.shift_indexes(-(start as i64))?;
+
+        let track = &mut self.tracks[0];
+        track.indexes.retain(|index| index.id != 0);
+        track.pregap = Some(sectors_to_cuestamp(existing + start - pregap));
+        self.size = Some(self.size.unwrap_or(0).saturating_sub(bytes));
+        self.compute_track_ranges();
+        Ok(bytes)
+    }
+
+    // Moves every index by sectors of the first track's size, which only lines up with the other tracks when they
+    // use the same size
+    fn shift_indexes(&mut self, sectors: i64) -> Result<(), CueParseError> {
+        let sector_size = self.tracks.first().map_or(SECTOR_SIZE, |track| track.sector_size);
+        if sectors != 0 && self.tracks.iter().any(|track| track.sector_size != sector_size) {
+            return Err(CueParseError::Unmergeable(format!(
+                "{} mixes sector sizes, so its pregap can't be moved",
+                self.filename.display()
+            )));
+        }
+        for index in self.tracks.iter_mut().flat_map(|track| &mut track.indexes) {
+            index.file_offset = (index.file_offset as i64 + sectors).clamp(0, u32::MAX as i64) as u32;
+            index.stamp = sectors_to_cuestamp(index.file_offset);
+        }
+        Ok(())
+    }
+
+    /// The tracks stored in the bin, in cue sheet order.
+    pub fn iter_tracks(&self) -> impl Iterator<Item = &Track> {
+        self.tracks.iter()
+    }
+
+    /// The disc title, from a `TITLE` command before any track, without its quotes.
+    pub fn title(&self) -> Option<&str> {
+        self.metadata.iter().find_map(|command| {
+            let title = command.strip_prefix("TITLE ")?.trim();
+            Some(title.strip_prefix('"').and_then(|title| title.strip_suffix('"')).unwrap_or(title))
+        })
+    }
+
+    /// The tracks of the bin ordered by number rather than by position in the cue sheet.
+    pub fn tracks_sorted(&self) -> Vec<&Track> {
+        let mut tracks: Vec<&Track> = self.tracks.iter().collect();
+        tracks.sort_by_key(|track| track.num);
+        tracks
+    }
+
+    // Indexes are stored in the bin in cue sheet order, so a stamp earlier than the one before it can't be laid out.
+    // The indexes of a track also have to be in number order, or its pregap would come after its start
+    fn check_index_order(&self) -> Result<(), CueParseError> {
+        let out_of_order = |previous_track: &Track, previous_index: &Index, track: &Track, index: &Index| {
+            CueParseError::NonMonotonicIndex {
+                filename: self.filename.clone(),
+                previous_track: previous_track.num,
+                previous_index: previous_index.id,
+                track: track.num,
+                index: index.id,
+                line_number: None,
+            }
+        };
+        let mut previous: Option<(&Track, &Index)> = None;
+        for track in &self.tracks {
+            for (i, index) in track.indexes.iter().enumerate() {
+                if let Some((previous_track, previous_index)) = previous {
+                    if index.file_offset < previous_index.file_offset {
+                        return Err(out_of_order(previous_track, previous_index, track, index));
+                    }
+                }
+                if let Some(previous_index) = out_of_order_index(&track.indexes[..i], index) {
+                    return Err(out_of_order(track, previous_index, track, index));
+                }
+                previous = Some((track, index));
+            }
+        }
+        Ok(())
+    }
+
+    // Every track needs an INDEX 01 to start at. When lenient, a track with only an INDEX 00 starts there instead
+    fn check_index_01(&mut self, lenient: bool) -> Result<(), CueParseError> {
+        for track in &mut self.tracks {
+            if track.index(1).is_some() {
+                continue;
+            }
+            let pregap = track.indexes.iter().position(|index| index.id == 0);
+            match pregap {
+                Some(position) if lenient => {
+                    let pregap = &track.indexes[position];
+                    let index = Index::new(1, pregap.stamp.clone(), pregap.file_offset);
+                    warn!("track {:02} has no INDEX 01, starting it at its INDEX 00", track.num);
+                    track.indexes.insert(position + 1, index);
+                }
+                _ => {
+                    return Err(CueParseError::MissingIndex01 { filename: self.filename.clone(), track: track.num });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Track lengths are in sectors of the track's own size, so a bin mixing sector sizes can have a track end past
+    // the byte where the next one starts, even with the stamps in order
+    fn check_track_overlaps(&self) -> Result<(), CueParseError> {
+        for pair in self.tracks.windows(2) {
+            let (start, end) = pair[0].byte_range(pair[0].sector_size);
+            let (next_start, next_end) = pair[1].byte_range(pair[1].sector_size);
+            if let Some(end) = end.filter(|end| *end > next_start) {
+                return Err(CueParseError::OverlappingTracks {
+                    filename: self.filename.clone(),
+                    track: (pair[0].num, start, end),
+                    next_track: (pair[1].num, next_start, next_end.unwrap_or(next_start)),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    // Each track runs until the next one starts, the last one until the end of the bin
+    fn compute_track_ranges(&mut self) {
+        // The size is read once when the bin is opened, or worked out from the stamps of a zero filled bin, so a
+        // missing one here would make the last track run to sector zero
+        debug_assert!(self.size.is_some(), "size of {} unknown before its track ranges", self.filename.display());
+        let last_sector_size = self.tracks.last().map_or(SECTOR_SIZE, |track| track.sector_size);
+        let file_sectors = sector_count(self.size.unwrap_or(0), last_sector_size);
+        // A track's data starts at its INDEX 00 pregap when it has one, so the pregap stays with it, otherwise at
+        // INDEX 01
+        let offsets: Vec<Option<u32>> = self.tracks.iter()
+            .map(|track| track.index(0).or(track.index(1)).or(track.indexes.first()).map(|index| index.file_offset))
+            .collect();
+
+        for (i, track) in self.tracks.iter_mut().enumerate() {
+            let next_offset = match offsets.get(i + 1) {
+                Some(next_offset) => *next_offset,
+                None => Some(file_sectors),
+            };
+
+            track.file_offset = offsets[i];
+            track.sectors = match (track.file_offset, next_offset) {
+                (Some(offset), Some(next_offset)) => next_offset.checked_sub(offset),
+                _ => None,
+            };
+        }
+    }
+
+    // A bin that stops more than a sector short of its last index stamp is missing at least its last track, which is
+    // usually a dump cut short or the wrong file. Empty bins have been warned about already
+    fn check_size(&self, strict: bool) -> Result<(), CueParseError> {
+        let size = self.size.unwrap_or(0);
+        let sector_size = self.tracks.last().map_or(SECTOR_SIZE, |track| track.sector_size) as u64;
+        let expected = self.stamped_size();
+        if size == 0 || expected <= size + sector_size {
+            return Ok(());
+        }
+        if strict {
+            return Err(CueParseError::SizeMismatch { filename: self.filename.clone(), expected, found: size });
+        }
+        let missing = expected - size;
+        warn!(
+            "{} is {} bytes, {} bytes ({} sectors) less than its index stamps need",
+            self.filename.display(),
+            size,
+            missing,
+            missing / sector_size
+        );
+        Ok(())
+    }
+
+    // Bytes up to and including the sector of the last index, in sectors of the last track like the track ranges
+    fn stamped_size(&self) -> u64 {
+        let sector_size = self.tracks.last().map_or(SECTOR_SIZE, |track| track.sector_size) as u64;
+        let last_offset = self.tracks.iter().flat_map(|track| &track.indexes).map(|index| index.file_offset).max();
+        last_offset.map_or(0, |offset| (offset as u64 + 1) * sector_size)
+    }
+
+    /// Length of the bin in sectors, which is where the next bin starts once merged.
+    pub fn sectors(&self) -> u32 {
+        match self.tracks.last() {
+            Some(track) => track.file_offset.unwrap_or(0).saturating_add(track.sectors.unwrap_or(0)),
+            None => sector_count(self.size.unwrap_or(0), SECTOR_SIZE),
+        }
+    }
+}
+
+impl fmt::Display for BinFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "-- File --")?;
+        write!(f, "\nFilename: {}", self.filename.display())?;
+        write!(f, "\nFormat: {}", self.format)?;
+        write!(f, "\nSize: {} bytes", self.size.unwrap_or(0))?;
+        write!(f, "\nTracks: {}", self.tracks.len())?;
+        for track in self.iter_tracks() {
+            write!(f, "\n{}", track)?;
+        }
+        Ok(())
+    }
+}
+
+/// Line ending used by a cue sheet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineEnding {
+    Lf,
+    Crlf,
+}
+
+impl LineEnding {
+    /// The convention of the platform, CRLF on Windows and LF elsewhere.
+    pub fn native() -> LineEnding {
+        if cfg!(windows) { LineEnding::Crlf } else { LineEnding::Lf }
+    }
+
+    /// The line ending of the cue sheet at `cue_path`, or the [`native`](LineEnding::native) one for stdin (`-`)
+    /// or a cue sheet without any line break.
+    pub fn detect(cue_path: &str) -> io::Result<LineEnding> {
+        if cue_path == "-" {
+            return Ok(LineEnding::native());
+        }
+        let contents = fs::read(cue_path)?;
+        match contents.iter().position(|byte| *byte == b'\n') {
+            Some(0) => Ok(LineEnding::Lf),
+            Some(i) if contents[i - 1] == b'\r' => Ok(LineEnding::Crlf),
+            Some(_) => Ok(LineEnding::Lf),
+            None => Ok(LineEnding::native()),
+        }
+    }
+
+    /// Converts `text`, written with LF line endings, to this line ending.
+    pub fn apply(&self, text: &str) -> String {
+        match self {
+            LineEnding::Lf => text.to_string(),
+            LineEnding::Crlf => text.replace('\n', "\r\n"),
+        }
+    }
+}
+
+/// Why a cue sheet line couldn't be parsed.
+#[derive(Debug)]
+pub enum CueLineError {
+    InvalidTrackNumber,
+    InvalidIndexNumber,
+    InvalidTimestamp(&'static str),
+    InvalidStatement(&'static str),
+    Unsupported(&'static str),
+}
+
+impl fmt::Display for CueLineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CueLineError::InvalidTrackNumber => write!(f, "invalid track number"),
+            CueLineError::InvalidIndexNumber => write!(f, "invalid index number"),
+            CueLineError::InvalidTimestamp(reason) => write!(f, "invalid timestamp ({})", reason),
+            CueLineError::InvalidStatement(reason) => write!(f, "invalid statement ({})", reason),
+            CueLineError::Unsupported(what) => write!(f, "{} isn't supported", what),
+        }
+    }
+}
+
+/// Error returned when a cue sheet can't be read or parsed.
+#[derive(Debug)]
+pub enum CueParseError {
+    Io(io::Error),
+    UnknownTrackType(String),
+    InvalidLine {
+        line_number: usize,
+        line_text: String,
+        kind: CueLineError,
+    },
+    /// Every bin referenced by the cue sheet that couldn't be read, by absolute path
+    MissingBinFiles(Vec<(PathBuf, io::Error)>),
+    /// Two tracks of `filename` whose byte ranges overlap, as the track number along with its start and end byte
+    OverlappingTracks {
+        filename: PathBuf,
+        track: (u32, u64, u64),
+        next_track: (u32, u64, u64),
+    },
+    /// An index of `filename` whose stamp is out of order with that of an index listed before it, either earlier than
+    /// the index before it in cue sheet order or, within a track, not in index number order. The line of the later
+    /// index is given when it's known
+    NonMonotonicIndex {
+        filename: PathBuf,
+        previous_track: u32,
+        previous_index: u32,
+        track: u32,
+        index: u32,
+        line_number: Option<usize>,
+    },
+    /// A track of `filename` without an `INDEX 01`, which is where the track starts
+    MissingIndex01 {
+        filename: PathBuf,
+        track: u32,
+    },
+    /// A bin that is zero bytes long, which is usually a dump that didn't finish
+    EmptyBinFile(PathBuf),
+    /// The first track whose number isn't the one after the track listed before it, counting from 1
+    TrackNumbering {
+        expected: u32,
+        found: u32,
+    },
+    /// A bin of `found` bytes that ends more than a sector before the `expected` bytes its index stamps need
+    SizeMismatch {
+        filename: PathBuf,
+        expected: u64,
+        found: u64,
+    },
+    /// Bins that can't be written out as a single merged bin and cue sheet, and why
+    Unmergeable(String),
+    /// A cue sheet without any `FILE`, or the bin of a `FILE` without any `TRACK`, which would merge into nothing
+    NoTracks(Option<PathBuf>),
+}
+
+impl fmt::Display for CueParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CueParseError::Io(err) => write!(f, "cannot read cue file: {}", err),
+            CueParseError::UnknownTrackType(track_type) => write!(f, "unknown track type {}", track_type),
+            CueParseError::InvalidLine { line_number, line_text, kind } => {
+                write!(f, "line {}: {} in \"{}\"", line_number, kind, line_text.trim())
+            }
+            CueParseError::MissingBinFiles(missing) => {
+                write!(f, "cannot read {} of the bin files referenced by the cue sheet:", missing.len())?;
+                for (path, err) in missing {
+                    write!(f, "\n  {}: {}", path.display(), err)?;
+                }
+                Ok(())
+            }
+            CueParseError::OverlappingTracks { filename, track, next_track } => write!(
+                f,
+                "track {:02} (bytes {}-{}) overlaps track {:02} (bytes {}-{}) in {}",
+                track.0,
+                track.1,
+                track.2,
+                next_track.0,
+                next_track.1,
+                next_track.2,
+                filename.display()
+            ),
+            CueParseError::NonMonotonicIndex {
+                filename,
+                previous_track,
+                previous_index,
+                track,
+                index,
+                line_number,
+            } => {
+                if let Some(line_number) = line_number {
+                    write!(f, "line {}: ", line_number)?;
+                }
+                write!(
+                    f,
+                    "track {:02} INDEX {:02} is out of order with track {:02} INDEX {:02} in {}",
+                    track,
+                    index,
+                    previous_track,
+                    previous_index,
+                    filename.display()
+                )
+            }
+            CueParseError::MissingIndex01 { filename, track } => {
+                write!(f, "track {:02} has no INDEX 01 in {}", track, filename.display())
+            }
+            CueParseError::EmptyBinFile(filename) => write!(f, "{} is empty", filename.display()),
+            CueParseError::TrackNumbering { expected, found } => {
+                write!(f, "track {:02} is listed where track {:02} was expected", found, expected)
+            }
+            CueParseError::SizeMismatch { filename, expected, found } => write!(
+                f,
+                "{} is {} bytes but its index stamps need at least {} bytes",
+                filename.display(),
+                found,
+                expected
+            ),
+            CueParseError::Unmergeable(reason) => write!(f, "{}", reason),
+            CueParseError::NoTracks(None) => write!(f, "the cue sheet has no FILE"),
+            CueParseError::NoTracks(Some(filename)) => write!(f, "{} has no TRACK", filename.display()),
+        }
+    }
+}
+
+impl error::Error for CueParseError {}
+
+impl From<io::Error> for CueParseError {
+    fn from(err: io::Error) -> Self {
+        CueParseError::Io(err)
+    }
+}
+
+impl From<CueParseError> for io::Error {
+    fn from(err: CueParseError) -> Self {
+        match err {
+            CueParseError::Io(err) => err,
+            err => io::Error::new(io::ErrorKind::InvalidData, err.to_string()),
+        }
+    }
+}
+
+/// Splits a cue sheet track type into its mode and sector size suffix, e.g. `MODE1` and `2048` for `MODE1/2048`.
+///
+/// `AUDIO` and `CDG` have no suffix. A suffix that isn't a number is returned as `None` along with the whole type.
+pub fn split_track_type(track_type: &str) -> (&str, Option<u32>) {
+    match track_type.split_once('/') {
+        Some((mode, size)) => match size.parse::<u32>() {
+            Ok(size) => (mode, Some(size)),
+            Err(_) => (track_type, None),
+        },
+        None => (track_type, None),
+    }
+}
+
+/// Bytes per sector for a cue sheet track type such as `AUDIO` or `MODE1/2048`.
+///
+/// The size comes from the suffix of the type when it has one, as long as it's a size the mode can be stored in.
+pub fn sector_size(track_type: &str) -> Result<u32, CueParseError> {
+    match split_track_type(track_type) {
+        ("AUDIO", None) => Ok(2352),
+        ("CDG", None) => Ok(2448),
+        ("MODE1", Some(size @ (2048 | 2352))) => Ok(size),
+        ("MODE2", Some(size @ (2048 | 2324 | 2336 | 2352))) => Ok(size),
+        ("CDI", Some(size @ (2336 | 2352))) => Ok(size),
+        _ => Err(CueParseError::UnknownTrackType(track_type.to_string())),
+    }
+}
+
+/// Kind of disc a cue sheet describes, going by its track types.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiscMode {
+    /// Only audio tracks, like a music CD
+    CdDa,
+    /// At least one `MODE1` data track and no `MODE2` or `CDI` track
+    CdRom,
+    /// At least one `MODE2` or `CDI` track, as on PlayStation and Saturn discs
+    CdRomXa,
+}
+
+impl DiscMode {
+    /// Disc mode of a disc with the given track types, e.g. `MODE2/2352` and `AUDIO`.
+    pub fn from_track_types<'a>(track_types: impl IntoIterator<Item = &'a str>) -> DiscMode {
+        let mut disc_mode = DiscMode::CdDa;
+        for track_type in track_types {
+            match split_track_type(track_type).0 {
+                "MODE2" | "CDI" => return DiscMode::CdRomXa,
+                "MODE1" => disc_mode = DiscMode::CdRom,
+                _ => {}
+            }
+        }
+        disc_mode
+    }
+}
+
+impl fmt::Display for DiscMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DiscMode::CdDa => write!(f, "CD-DA"),
+            DiscMode::CdRom => write!(f, "CD-ROM"),
+            DiscMode::CdRomXa => write!(f, "CD-ROM XA"),
+        }
+    }
+}
+
+/// Reads the cue sheet at `cue_path` with [`get_cd_from_cue`] and tells which kind of disc it describes.
+pub fn disc_mode(cue_path: &str) -> io::Result<DiscMode> {
+    let cd = get_cd_from_cue(cue_path)?;
+    Ok(DiscMode::from_track_types(cd.files.iter().flat_map(|file| &file.tracks).map(|track| track.format.as_str())))
+}
+
+/// Whether a track type holds audio rather than data, i.e. `AUDIO` or `CDG`.
+pub fn is_audio_track(track_type: &str) -> bool {
+    matches!(track_type, "AUDIO" | "CDG")
+}
+
+/// Converts an `MM:SS:FF` cue timestamp to a sector count, at 75 sectors per second.
+///
+/// Seconds must be below 60 and frames below 75.
+pub fn cuestamp_to_sectors(timestamp: &str) -> Result<u32, &'static str> {
+    stamp_sectors(timestamp, true)
+}
+
+// Some tools write stamps such as 00:00:75, which still add up to a position when the range isn't checked
+fn stamp_sectors(timestamp: &str, check_range: bool) -> Result<u32, &'static str> {
+    if let Some(caps) = CUESTAMP_PATTERN.captures(timestamp) {
+        let minutes = caps.get(1).ok_or("Invalid timestamp")?.as_str().parse::<u32>().map_err(|_| "Invalid minutes")?;
+        let seconds = caps.get(2).ok_or("Invalid timestamp")?.as_str().parse::<u32>().map_err(|_| "Invalid seconds")?;
+        let frames = caps.get(3).ok_or("Invalid timestamp")?.as_str().parse::<u32>().map_err(|_| "Invalid frames")?;
+        if check_range && seconds >= 60 {
+            return Err("Seconds must be below 60");
+        }
+        if check_range && frames >= 75 {
+            return Err("Frames must be below 75");
+        }
+
+        // Merged images can run well past 99 minutes, so guard against stamps too large for a u32
+        let sectors = frames as u64 + (seconds as u64 * 75) + (minutes as u64 * 60 * 75);
+        u32::try_from(sectors).map_err(|_| "Timestamp is out of range")
+    } else {
+        Err("Timestamp does not match pattern")
+    }
+}
+
+/// Converts a sector count to an `MM:SS:FF` cue timestamp. Minutes are not capped at two digits.
+pub fn sectors_to_cuestamp(sectors: u32) -> String {
+    let minutes = sectors / (60 * 75);
+    let seconds = (sectors / 75) % 60;
+    let frames = sectors % 75;
+
+    format!("{:02}:{:02}:{:02}", minutes, seconds, frames)
+}
+
+/// Length in sectors of all of `bin_files` together, i.e. of the merged bin.
+///
+/// This only counts what the bins hold. On the disc, the lead-in and the 2 second (150 sector) pause before track 1
+/// come first and the lead-out follows the last track, and silence from `PREGAP` and `POSTGAP` commands is generated
+/// on playback, so none of it is included. A 74 minute disc holds 333,000 sectors of tracks and an 80 minute one
+/// 360,000.
+pub fn total_sectors(bin_files: &[BinFile]) -> u32 {
+    bin_files.iter().fold(0, |total, bin_file| total.saturating_add(bin_file.sectors()))
+}
+
+/// [`total_sectors`] as an `MM:SS:FF` length.
+pub fn total_length(bin_files: &[BinFile]) -> String {
+    sectors_to_cuestamp(total_sectors(bin_files))
+}
+
+// Zero pads a timestamp such as 0:2:0, which the parser has already checked, carrying frames and seconds that are
+// out of range when it was lenient
+fn canonical_cuestamp(timestamp: &str) -> String {
+    stamp_sectors(timestamp, false).map_or(timestamp.to_string(), sectors_to_cuestamp)
+}
+
+// When lenient, a stamp out of range is only warned about
+fn parse_cuestamp(timestamp: &str, lenient: bool, line_number: usize) -> Result<u32, &'static str> {
+    match cuestamp_to_sectors(timestamp) {
+        Err(reason) if lenient => {
+            let sectors = stamp_sectors(timestamp, false)?;
+            warn!("line {}: {} in {}, reading it as {}", line_number, reason, timestamp, sectors_to_cuestamp(sectors));
+            Ok(sectors)
+        }
+        result => result,
+    }
+}
+
+// Appends a TRACK block to a regenerated cue sheet, with each index moved to the sector returned by index_sector
+pub(crate) fn push_track(cuesheet: &mut String, track: &Track, index_sector: impl Fn(&Index) -> u32) {
+    *cuesheet += &format!("  TRACK {:02} {}\n", track.num, track.track_type);
+    if !track.flags.is_empty() {
+        *cuesheet += &format!("    FLAGS {}\n", track.flags.join(" "));
+    }
+    for command in &track.metadata {
+        *cuesheet += &format!("    {}\n", command);
+    }
+    if let Some(pregap) = &track.pregap {
+        *cuesheet += &format!("    PREGAP {}\n", canonical_cuestamp(pregap));
+    }
+    for index in &track.indexes {
+        *cuesheet += &format!("    INDEX {:02} {}\n", index.id, sectors_to_cuestamp(index_sector(index)));
+    }
+    if let Some(postgap) = &track.postgap {
+        *cuesheet += &format!("    POSTGAP {}\n", canonical_cuestamp(postgap));
+    }
+}
+
+/// Prints the parsed files, tracks and indexes of a cue sheet.
+pub fn print_bin_files(bin_files: &Vec<BinFile>) {
+    for bin_file in bin_files{
+        println!("{}", bin_file);
+    }
+}
+
+/// Parses a cue sheet into the bin files it references, with track offsets and lengths computed.
+///
+/// A `cue_path` of `-` reads the cue sheet from stdin. Bin paths are resolved against the cue sheet's directory,
+/// which is the current directory for stdin.
+pub fn get_bin_from_cue(cue_path : &str) -> Result<Vec<BinFile>, BinmergeError> {
+    get_bin_from_cue_in(cue_path, None)
+}
+
+/// Like [`get_bin_from_cue`], resolving bin paths against `basedir` instead of the cue sheet's directory when set.
+pub fn get_bin_from_cue_in(cue_path: &str, basedir: Option<&Path>) -> Result<Vec<BinFile>, BinmergeError> {
+    let (bin_files, _) = get_bin_from_cue_with(cue_path, &ParseOptions { basedir, ..ParseOptions::default() })?;
+    Ok(bin_files)
+}
+
+/// Like [`get_bin_from_cue_in`], carrying on past bins that can't be read so they can be zero filled, and returning
+/// their paths along with the bin files.
+///
+/// A missing bin is given the size its index stamps account for. Nothing tells how long its last track is, so that
+/// track is a single sector.
+pub fn get_bin_from_cue_zero_filled(
+    cue_path: &str,
+    basedir: Option<&Path>,
+) -> Result<(Vec<BinFile>, Vec<PathBuf>), BinmergeError> {
+    get_bin_from_cue_with(cue_path, &ParseOptions { basedir, zero_fill_missing: true, ..ParseOptions::default() })
+}
+
+/// How [`get_bin_from_cue_with`] deals with cue sheets that aren't quite right. The default is as strict as
+/// [`get_bin_from_cue`].
+#[derive(Clone, Debug, Default)]
+pub struct ParseOptions<'a> {
+    /// Directory bin paths are resolved against instead of the cue sheet's directory
+    pub basedir: Option<&'a Path>,
+    /// Keep bins that can't be read so they can be zero filled, see [`get_bin_from_cue_zero_filled`]
+    pub zero_fill_missing: bool,
+    /// Give a track with an `INDEX 00` but no `INDEX 01` an `INDEX 01` at the same stamp, and warn about stamps with
+    /// seconds or frames out of range and empty bins, instead of failing
+    pub lenient: bool,
+    /// Fail rather than warn when the tracks aren't numbered 1 to N in cue sheet order
+    pub strict_numbering: bool,
+    /// Fail rather than warn when a bin ends more than a sector before its last index stamp
+    pub strict_sizes: bool,
+    /// Prefixes of `FILE` paths to replace, as `(from, to)`, before the paths are resolved. The first `from` that
+    /// starts a path, ignoring ASCII case like DOS and Windows do, is replaced by its `to`.
+    pub remap: &'a [(String, String)],
+}
+
+/// Like [`get_bin_from_cue`] with every option of [`ParseOptions`], returning the bins to zero fill along with the
+/// bin files.
+///
+/// With the `toc` feature, a `cue_path` ending in `.toc` is read as a cdrdao TOC file by `get_bin_from_toc`, which
+/// is how every command that takes a cue sheet takes a TOC file too.
+pub fn get_bin_from_cue_with(
+    cue_path: &str,
+    options: &ParseOptions,
+) -> Result<(Vec<BinFile>, Vec<PathBuf>), BinmergeError> {
+    #[cfg(feature = "toc")]
+    if crate::toc::is_toc(cue_path) {
+        return crate::toc::get_bin_from_toc(cue_path, options);
+    }
+    let reader: Box<dyn BufRead> = if cue_path == "-" {
+        Box::new(io::stdin().lock())
+    } else {
+        Box::new(io::BufReader::new(File::open(cue_path).map_err(CueParseError::Io)?))
+    };
+
+    // Bin paths are relative to the cue sheet's directory, while absolute ones replace it when joined
+    let cue_dir = match options.basedir {
+        Some(basedir) => basedir,
+        None if cue_path == "-" => Path::new("."),
+        None => Path::new(cue_path).parent().unwrap_or(Path::new(".")),
+    };
+
+    Ok(parse_cue_lines(reader, cue_dir, options)?)
+}
+
+/// Parses cue sheet text read from `reader` into the bin files it references, as [`get_bin_from_cue`] does for a
+/// file. Bin paths are resolved against `basedir`.
+pub fn parse_cue<R: BufRead>(reader: R, basedir: &Path) -> Result<Vec<BinFile>, BinmergeError> {
+    let (bin_files, _) = parse_cue_lines(reader, basedir, &ParseOptions::default())?;
+    Ok(bin_files)
+}
+
+// The path a FILE line of a cue sheet in dir refers to, once the first matching remap is applied
+pub(crate) fn bin_path(dir: &Path, bin: &str, remap: &[(String, String)]) -> PathBuf {
+    let remapped = remap.iter().find_map(|(from, to)| {
+        let prefix = bin.get(..from.len()).filter(|prefix| prefix.eq_ignore_ascii_case(from))?;
+        Some(format!("{}{}", to, &bin[prefix.len()..]))
+    });
+    // Cue sheets made on Windows use backslashes, which are just another filename character elsewhere
+    dir.join(remapped.as_deref().unwrap_or(bin).replace('\\', path::MAIN_SEPARATOR_STR))
+}
+
+// The bins are resolved against cue_dir, whatever the basedir of the options
+fn parse_cue_lines<R: BufRead>(
+    reader: R,
+    cue_dir: &Path,
+    options: &ParseOptions,
+) -> Result<(Vec<BinFile>, Vec<PathBuf>), CueParseError> {
+    let lenient = options.lenient;
+    let mut bin_files: Vec<BinFile> = Vec::new();
+    let start = Instant::now();
+
+    let mut current_file_index: Option<usize> = None;
+    let mut current_track_index: Option<usize> = None;
+    let mut disc_metadata: Vec<String> = Vec::new();
+    let mut missing_bin_files: Vec<(PathBuf, io::Error)> = Vec::new();
+
+    for (line_index, line) in reader.lines().enumerate() {
+        let line = line?;
+        // Windows tools tend to start cue sheets with a BOM, and to leave trailing whitespace behind
+        let line = if line_index == 0 { line.trim_start_matches('\u{feff}') } else { &line }.trim_end().to_string();
+        let invalid_line = |kind| CueParseError::InvalidLine {
+            line_number: line_index + 1,
+            line_text: line.clone(),
+            kind,
+        };
+
+        // Process file lines
+        if let Some(caps) = FILE_PATTERN.captures(&line) {
+            let start_bin_file = Instant::now();
+            
+            let bin = caps.get(1).or(caps.get(2)).or(caps.get(3));
+            if let (Some(bin), Some(format)) = (bin, caps.get(4)) {
+                let bin_file_path = bin_path(cue_dir, bin.as_str(), options.remap);
+                let format = format.as_str().to_ascii_uppercase();
+                let mut current_bin_file = open_bin_file(bin_file_path, format, &mut missing_bin_files);
+                current_bin_file.cue_filename = bin.as_str().to_string();
+                current_bin_file.metadata = std::mem::take(&mut disc_metadata);
+                bin_files.push(current_bin_file);
+                current_file_index = Some(bin_files.len() - 1);
+                current_track_index = None;
+
+                let duration_bin_file = start_bin_file.elapsed();
+                debug!("Time elapsed in BinFile::new() is: {:?}", duration_bin_file);
+
+                continue;
+            }
+        }
+        // Process track lines
+        if let Some(caps) = TRACK_PATTERN.captures(&line) {
+            let start_track = Instant::now();
+
+            if let (Some(track_number_match), Some(track_type_match)) = (caps.get(1), caps.get(2)) {
+                let track_number = track_number_match.as_str().parse::<u32>()
+                    .map_err(|_| invalid_line(CueLineError::InvalidTrackNumber))?;
+                let track_type = track_type_match.as_str().to_ascii_uppercase();
+                let track_sector_size = sector_size(&track_type)?;
+
+                let file_index = current_file_index
+                    .ok_or_else(|| invalid_line(CueLineError::InvalidStatement("TRACK before any FILE")))?;
+                let current_track = Track::new(track_number, track_type, track_sector_size);
+                bin_files[file_index].tracks.push(current_track);
+                current_track_index = Some(bin_files[file_index].tracks.len() - 1);
+
+                let duration_tracks = start_track.elapsed();
+                debug!("Time elapsed in Track::new() is: {:?}", duration_tracks);
+
+                continue;
+            }
+        }
+        // Process index lines
+        if let Some(caps) = INDEX_PATTERN.captures(&line) {
+            if let (Some(index_number_match), Some(timestamp_match)) = (caps.get(1), caps.get(2)) {
+                let index_number = index_number_match.as_str().parse::<u32>()
+                    .map_err(|_| invalid_line(CueLineError::InvalidIndexNumber))?;
+                let timestamp = timestamp_match.as_str().to_string();
+                let file_offset = parse_cuestamp(&timestamp, lenient, line_index + 1) // Convert timestamp to sectors
+                    .map_err(|reason| invalid_line(CueLineError::InvalidTimestamp(reason)))?;
+
+                match (current_file_index, current_track_index) {
+                    (Some(file_index), Some(track_index)) => {
+                        let current_index = Index::new(index_number, timestamp, file_offset);
+                        let bin_file = &mut bin_files[file_index];
+                        let track = &mut bin_file.tracks[track_index];
+                        // Checked here as well as once the bins are read, to give the line
+                        if let Some(previous_index) = out_of_order_index(&track.indexes, &current_index) {
+                            return Err(CueParseError::NonMonotonicIndex {
+                                filename: bin_file.filename.clone(),
+                                previous_track: track.num,
+                                previous_index: previous_index.id,
+                                track: track.num,
+                                index: index_number,
+                                line_number: Some(line_index + 1),
+                            });
+                        }
+                        track.indexes.push(current_index);
+                    }
+                    _ => return Err(invalid_line(CueLineError::InvalidStatement("INDEX before any TRACK"))),
+                }
+
+                continue;
+            }
+        }
+        // Process flags lines
+        if let Some(caps) = FLAGS_PATTERN.captures(&line) {
+            if let Some(flags_match) = caps.get(1) {
+                if let Some(file_index) = current_file_index {
+                    if let Some(track_index) = current_track_index {
+                        let flags = flags_match.as_str().split_whitespace().map(|flag| flag.to_ascii_uppercase());
+                        bin_files[file_index].tracks[track_index].flags.extend(flags);
+                    }
+                }
+
+                continue;
+            }
+        }
+        // Process pregap and postgap lines
+        if let Some(caps) = GAP_PATTERN.captures(&line) {
+            if let (Some(gap_match), Some(timestamp_match)) = (caps.get(1), caps.get(2)) {
+                let timestamp = timestamp_match.as_str().to_string();
+                parse_cuestamp(&timestamp, lenient, line_index + 1)
+                    .map_err(|reason| invalid_line(CueLineError::InvalidTimestamp(reason)))?;
+
+                if let Some(file_index) = current_file_index {
+                    if let Some(track_index) = current_track_index {
+                        let track = &mut bin_files[file_index].tracks[track_index];
+                        match gap_match.as_str().to_ascii_uppercase().as_str() {
+                            "PREGAP" => track.pregap = Some(timestamp),
+                            _ => track.postgap = Some(timestamp),
+                        }
+                    }
+                }
+
+                continue;
+            }
+        }
+        // Kept as metadata, a FILE, TRACK or INDEX line the patterns didn't take would leave the tracks after it in
+        // the wrong bin
+        let command = line.trim();
+        let keyword = command.split_whitespace().next().unwrap_or_default().to_ascii_uppercase();
+        let expected = match keyword.as_str() {
+            "FILE" => Some("expected FILE \"path\" TYPE, with paths holding spaces quoted"),
+            "TRACK" => Some("expected TRACK NN TYPE"),
+            "INDEX" => Some("expected INDEX NN MM:SS:FF"),
+            _ => None,
+        };
+        if let Some(expected) = expected {
+            return Err(invalid_line(CueLineError::InvalidStatement(expected)));
+        }
+        // Keep everything else verbatim, in the scope it appeared in
+        if command.is_empty() {
+            continue;
+        }
+        match (current_file_index, current_track_index) {
+            (Some(file_index), Some(track_index)) => {
+                bin_files[file_index].tracks[track_index].metadata.push(command.to_string())
+            }
+            (Some(file_index), None) => bin_files[file_index].metadata.push(command.to_string()),
+            _ => disc_metadata.push(command.to_string()),
+        }
+    }
+    let duration = start.elapsed();
+    debug!("Time elapsed in get_bin_from_cue() is: {:?}", duration);
+
+    let zero_filled = check_bin_files(&mut bin_files, missing_bin_files, options)?;
+    Ok((bin_files, zero_filled))
+}
+
+// Keeps going past a missing bin so they can all be reported at once, the bin being left without a size
+pub(crate) fn open_bin_file(
+    bin_file_path: PathBuf,
+    format: String,
+    missing_bin_files: &mut Vec<(PathBuf, io::Error)>,
+) -> BinFile {
+    match BinFile::new(bin_file_path.clone(), format.clone()) {
+        Ok(bin_file) => bin_file,
+        Err(e) => {
+            missing_bin_files.push((path::absolute(&bin_file_path).unwrap_or(bin_file_path.clone()), e));
+            BinFile::with_size(bin_file_path, format, None)
+        }
+    }
+}
+
+// Everything checked once all the bins of a cue sheet are known, which also works out the track ranges. Returns the
+// bins to zero fill
+pub(crate) fn check_bin_files(
+    bin_files: &mut [BinFile],
+    missing_bin_files: Vec<(PathBuf, io::Error)>,
+    options: &ParseOptions,
+) -> Result<Vec<PathBuf>, CueParseError> {
+    let ParseOptions { zero_fill_missing, lenient, strict_numbering, strict_sizes, .. } = *options;
+    if bin_files.is_empty() {
+        return Err(CueParseError::NoTracks(None));
+    }
+    if let Some(bin_file) = bin_files.iter().find(|bin_file| bin_file.tracks.is_empty()) {
+        return Err(CueParseError::NoTracks(Some(bin_file.filename.clone())));
+    }
+    if !missing_bin_files.is_empty() && !zero_fill_missing {
+        return Err(CueParseError::MissingBinFiles(missing_bin_files));
+    }
+    for bin_file in bin_files.iter().filter(|bin_file| bin_file.size == Some(0)) {
+        if !lenient {
+            return Err(CueParseError::EmptyBinFile(bin_file.filename.clone()));
+        }
+        warn!("{} is empty, its tracks will have no data", bin_file.filename.display());
+    }
+    // Only bins that couldn't be read have no size
+    let mut zero_filled = Vec::new();
+    for bin_file in bin_files.iter_mut().filter(|bin_file| bin_file.size.is_none()) {
+        bin_file.size = Some(bin_file.stamped_size());
+        zero_filled.push(bin_file.filename.clone());
+    }
+
+    for bin_file in bin_files.iter_mut() {
+        bin_file.check_index_order()?;
+        bin_file.check_index_01(lenient)?;
+        bin_file.compute_track_ranges();
+        bin_file.check_size(strict_sizes)?;
+        bin_file.check_track_overlaps()?;
+    }
+
+    // Tracks are laid out in the bins in cue sheet order whatever their numbers, so they are left in that order.
+    // Anything but 1 to N usually means a FILE went missing or the cue sheet was edited by hand though
+    let track_numbers = bin_files.iter().flat_map(|bin_file| &bin_file.tracks).map(|track| track.num);
+    if let Some((expected, found)) = (1..).zip(track_numbers).find(|(expected, found)| expected != found) {
+        if strict_numbering {
+            return Err(CueParseError::TrackNumbering { expected, found });
+        }
+        warn!("track {:02} is listed where track {:02} was expected, keeping the cue sheet order", found, expected);
+    }
+
+    Ok(zero_filled)
+}
+
+/// Parses a cue sheet with the `rcue` parser.
+pub fn get_cd_from_cue(cue_path: &str) -> io::Result<Cue> {
+    let contents = fs::read_to_string(cue_path)?;
+    let contents = contents.trim_start_matches('\u{feff}');
+    parse(&mut contents.as_bytes(), true).map_err(|err| match err {
+        CueError::Io(err) => err,
+        CueError::Parse(token) => io::Error::new(io::ErrorKind::InvalidData, format!("cannot parse cue file: {}", token)),
+    })
+}
+
+// rcue stores timestamps as durations, which have to be rounded back to whole frames
+fn duration_to_sectors(duration: Duration) -> u32 {
+    (duration.as_secs_f64() * 75.0).round() as u32
+}
+
+fn duration_to_cuestamp(duration: Duration) -> String {
+    sectors_to_cuestamp(duration_to_sectors(duration))
+}
+
+/// Parses the cue sheet at `cue_path` again with [`get_cd_from_cue`] and lists every `FILE`, `TRACK` and `INDEX`
+/// that the `rcue` parser reads differently from the `bin_files` parsed from it, one line each. Both parsers agree
+/// when the list is empty.
+pub fn cross_check_cue(cue_path: &str, bin_files: &[BinFile]) -> io::Result<Vec<String>> {
+    let cd = get_cd_from_cue(cue_path)?;
+    let mut differences = Vec::new();
+    if cd.files.len() != bin_files.len() {
+        differences.push(format!("{} FILE entries, but {} for rcue", bin_files.len(), cd.files.len()));
+    }
+    for (bin_file, file) in bin_files.iter().zip(&cd.files) {
+        if file.file != bin_file.cue_filename {
+            differences.push(format!("FILE {} is {} for rcue", bin_file.cue_filename, file.file));
+        }
+        if file.tracks.len() != bin_file.tracks.len() {
+            differences.push(format!(
+                "{} tracks in FILE {}, but {} for rcue",
+                bin_file.tracks.len(),
+                bin_file.cue_filename,
+                file.tracks.len()
+            ));
+        }
+        for (track, cd_track) in bin_file.tracks.iter().zip(&file.tracks) {
+            if cd_track.no.parse::<u32>().ok() != Some(track.num) {
+                differences.push(format!("track {:02} is track {} for rcue", track.num, cd_track.no));
+            }
+            if !cd_track.format.eq_ignore_ascii_case(&track.track_type) {
+                let track_type = &track.track_type;
+                differences.push(format!("track {:02} is {}, but {} for rcue", track.num, track_type, cd_track.format));
+            }
+            let indexes: Vec<String> = track.indexes.iter()
+                .map(|index| format!("INDEX {:02} {}", index.id, sectors_to_cuestamp(index.file_offset)))
+                .collect();
+            let cd_indexes: Vec<String> = cd_track.indices.iter()
+                .map(|(id, start)| {
+                    let id = id.parse::<u32>().map_or(id.clone(), |id| format!("{:02}", id));
+                    format!("INDEX {} {}", id, duration_to_cuestamp(*start))
+                })
+                .collect();
+            if indexes != cd_indexes {
+                differences.push(format!(
+                    "track {:02} has {}, but {} for rcue",
+                    track.num,
+                    indexes.join(", "),
+                    cd_indexes.join(", ")
+                ));
+            }
+        }
+    }
+    Ok(differences)
+}
+
+/// Prints the disc, files and tracks of a cue sheet parsed by [`get_cd_from_cue`].
+pub fn print_cd_summary(cd: &Cue) {
+    println!("CD title: {}", cd.title.as_deref().unwrap_or("none"));
+    println!("CD performer: {}", cd.performer.as_deref().unwrap_or("none"));
+    println!("CD catalog: {}", cd.catalog.as_deref().unwrap_or("none"));
+    println!("Files: {}", cd.files.len());
+
+    for file in &cd.files {
+        println!("-- File --");
+        println!("Filename: {}", file.file);
+        println!("Format: {}", file.format);
+        println!("Tracks: {}", file.tracks.len());
+
+        for track in &file.tracks {
+            println!("-- Track --");
+            println!("Track number: {}", track.no);
+            println!("Track type: {}", track.format);
+            println!("Track title: {}", track.title.as_deref().unwrap_or("none"));
+            println!("Track pregap: {}", track.pregap.map_or("none".to_string(), duration_to_cuestamp));
+            println!("Track postgap: {}", track.postgap.map_or("none".to_string(), duration_to_cuestamp));
+            for (index, start) in &track.indices {
+                println!("Index {}: {}", index, duration_to_cuestamp(*start));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::testing::TempDir;
+
+    // Parses a cue sheet whose bins don't exist, zero filled to the sizes their index stamps need
+    fn parse(cuesheet: &str) -> Result<Vec<BinFile>, CueParseError> {
+        parse_with(cuesheet, ParseOptions::default())
+    }
+
+    fn parse_with(cuesheet: &str, options: ParseOptions) -> Result<Vec<BinFile>, CueParseError> {
+        let options = ParseOptions { zero_fill_missing: true, ..options };
+        let (bin_files, _) = parse_cue_lines(Cursor::new(cuesheet), Path::new("missing"), &options)?;
+        Ok(bin_files)
+    }
+
+    fn render(track: &Track) -> String {
+        let mut cuesheet = String::new();
+        push_track(&mut cuesheet, track, |index| index.file_offset);
+        cuesheet
+    }
+
+    #[test]
+    fn cuestamp_round_trips_through_sectors() {
+        // Every stamp of the first two minutes, then a stride through the rest of a u32's range
+        for sectors in (0..9000).chain((9000..u32::MAX - 7919).step_by(7919)).chain([u32::MAX]) {
+            assert_eq!(cuestamp_to_sectors(&sectors_to_cuestamp(sectors)), Ok(sectors));
+        }
+    }
+
+    #[test]
+    fn cuestamp_converts_to_sectors() {
+        assert_eq!(cuestamp_to_sectors("00:00:00"), Ok(0));
+        assert_eq!(cuestamp_to_sectors("00:02:00"), Ok(150));
+        assert_eq!(cuestamp_to_sectors("74:59:74"), Ok(337_499));
+        assert_eq!(sectors_to_cuestamp(337_499), "74:59:74");
+        // Merged images run past 99 minutes, which keeps all its digits
+        assert_eq!(sectors_to_cuestamp(120 * 60 * 75), "120:00:00");
+        assert_eq!(cuestamp_to_sectors("120:00:00"), Ok(540_000));
+    }
+
+    #[test]
+    fn cuestamp_is_refused_out_of_range() {
+        assert!(cuestamp_to_sectors("00:00:75").is_err());
+        assert!(cuestamp_to_sectors("74:59:75").is_err());
+        assert!(cuestamp_to_sectors("99999999:00:00").is_err());
+        assert!(cuestamp_to_sectors("00:02").is_err());
+    }
+
+    #[test]
+    fn pregap_and_postgap_are_kept_around_the_indexes() {
+        let bin_files = parse(concat!(
+            "FILE \"game.bin\" BINARY\n",
+            "  TRACK 01 MODE1/2352\n",
+            "    INDEX 01 00:00:00\n",
+            "  TRACK 02 AUDIO\n",
+            "    PREGAP 00:02:00\n",
+            "    INDEX 01 00:10:00\n",
+            "    POSTGAP 0:1:0\n",
+        ))
+        .unwrap();
+        let track = &bin_files[0].tracks[1];
+        assert_eq!(track.pregap.as_deref(), Some("00:02:00"));
+        assert_eq!(track.postgap.as_deref(), Some("0:1:0"));
+        // A PREGAP isn't stored in the bin, so the track still starts at its INDEX 01
+        assert_eq!(track.file_offset, Some(750));
+        assert_eq!(
+            render(track),
+            "  TRACK 02 AUDIO\n    PREGAP 00:02:00\n    INDEX 01 00:10:00\n    POSTGAP 00:01:00\n"
+        );
+    }
+
+    #[test]
+    fn track_starts_at_its_pregap() {
+        let bin_files = parse(concat!(
+            "FILE \"game.bin\" BINARY\n",
+            "  TRACK 01 MODE1/2352\n",
+            "    INDEX 01 00:00:00\n",
+            "  TRACK 02 AUDIO\n",
+            "    INDEX 00 00:01:74\n",
+            "    INDEX 01 00:02:00\n",
+        ))
+        .unwrap();
+        let (track_1, track_2) = (&bin_files[0].tracks[0], &bin_files[0].tracks[1]);
+        assert_eq!(track_2.index(0).map(|index| index.file_offset), Some(149));
+        assert_eq!(track_2.index(1).map(|index| index.file_offset), Some(150));
+        assert_eq!(track_2.file_offset, Some(149));
+        assert_eq!(track_1.sectors, Some(149));
+        assert_eq!(track_2.byte_range(track_2.sector_size).0, 149 * 2352);
+    }
+
+    #[test]
+    fn indexes_out_of_number_order_are_refused() {
+        let err = parse(concat!(
+            "FILE \"game.bin\" BINARY\n",
+            "  TRACK 01 MODE1/2352\n",
+            "    INDEX 01 00:00:00\n",
+            "  TRACK 02 AUDIO\n",
+            "    INDEX 01 00:01:74\n",
+            "    INDEX 00 00:02:00\n",
+        ))
+        .err()
+        .unwrap();
+        assert!(matches!(
+            err,
+            CueParseError::NonMonotonicIndex { track: 2, index: 0, previous_index: 1, line_number: Some(6), .. }
+        ));
+        assert!(err.to_string().starts_with("line 6: track 02 INDEX 00 is out of order with track 02 INDEX 01"));
+    }
+
+    #[test]
+    fn file_paths_are_read_in_every_quoting_style() {
+        let bin_files = parse(concat!(
+            "FILE \"game (Track 1).bin\" BINARY\n",
+            "  TRACK 01 MODE1/2352\n",
+            "    INDEX 01 00:00:00\n",
+            "FILE 'game (Track 2).bin' BINARY\n",
+            "  TRACK 02 AUDIO\n",
+            "    INDEX 01 00:00:00\n",
+            "FILE track3.bin binary\n",
+            "  TRACK 03 AUDIO\n",
+            "    INDEX 01 00:00:00\n",
+        ))
+        .unwrap();
+        let names: Vec<&str> = bin_files.iter().map(|bin_file| bin_file.cue_filename.as_str()).collect();
+        assert_eq!(names, ["game (Track 1).bin", "game (Track 2).bin", "track3.bin"]);
+        assert_eq!(bin_files[1].filename, Path::new("missing").join("game (Track 2).bin"));
+        assert_eq!(bin_files[2].format, "BINARY");
+    }
+
+    #[test]
+    fn unquoted_path_with_spaces_is_refused() {
+        let err = parse("FILE game track 1.bin BINARY\n  TRACK 01 AUDIO\n    INDEX 01 00:00:00\n").err().unwrap();
+        assert!(matches!(err, CueParseError::InvalidLine { line_number: 1, .. }));
+    }
+
+    #[test]
+    fn bin_paths_are_resolved_against_the_cue_directory() {
+        assert_eq!(bin_path(Path::new(""), "game.bin", &[]), Path::new("game.bin"));
+        assert_eq!(bin_path(Path::new("cues"), "game.bin", &[]), Path::new("cues/game.bin"));
+        assert_eq!(bin_path(Path::new("cues"), "/bins/game.bin", &[]), Path::new("/bins/game.bin"));
+    }
+
+    #[test]
+    fn stamps_going_backwards_across_tracks_are_refused() {
+        let err = parse(concat!(
+            "FILE \"game.bin\" BINARY\n",
+            "  TRACK 01 MODE1/2352\n",
+            "    INDEX 01 00:00:00\n",
+            "  TRACK 02 AUDIO\n",
+            "    INDEX 01 00:05:00\n",
+            "  TRACK 03 AUDIO\n",
+            "    INDEX 01 00:03:00\n",
+        ))
+        .err()
+        .unwrap();
+        assert!(matches!(
+            err,
+            CueParseError::NonMonotonicIndex { previous_track: 2, track: 3, line_number: None, .. }
+        ));
+    }
+
+    const TRACK_3_BEFORE_2: &str = concat!(
+        "FILE \"game.bin\" BINARY\n",
+        "  TRACK 01 MODE1/2352\n",
+        "    INDEX 01 00:00:00\n",
+        "  TRACK 03 AUDIO\n",
+        "    INDEX 01 00:01:00\n",
+        "  TRACK 02 AUDIO\n",
+        "    INDEX 01 00:02:00\n",
+    );
+
+    #[test]
+    fn tracks_listed_out_of_order_keep_the_cue_order() {
+        let bin_files = parse(TRACK_3_BEFORE_2).unwrap();
+        let listed: Vec<u32> = bin_files[0].tracks.iter().map(|track| track.num).collect();
+        let sorted: Vec<u32> = bin_files[0].tracks_sorted().iter().map(|track| track.num).collect();
+        assert_eq!(listed, [1, 3, 2]);
+        assert_eq!(sorted, [1, 2, 3]);
+        assert_eq!(bin_files[0].tracks[1].sectors, Some(75));
+    }
+
+    #[test]
+    fn tracks_listed_out_of_order_are_refused_when_strict() {
+        let err = parse_with(TRACK_3_BEFORE_2, ParseOptions { strict_numbering: true, ..ParseOptions::default() });
+        assert!(matches!(err, Err(CueParseError::TrackNumbering { expected: 2, found: 3 })));
+    }
+
+    #[test]
+    fn tracks_overlapping_in_bytes_are_refused() {
+        // One 2352 byte sector of track 1 runs past the 2048 bytes where track 2 starts
+        let err = parse(concat!(
+            "FILE \"game.bin\" BINARY\n",
+            "  TRACK 01 MODE2/2352\n",
+            "    INDEX 01 00:00:00\n",
+            "  TRACK 02 MODE1/2048\n",
+            "    INDEX 01 00:00:01\n",
+        ))
+        .err()
+        .unwrap();
+        assert!(matches!(
+            err,
+            CueParseError::OverlappingTracks { track: (1, 0, 2352), next_track: (2, 2048, 4096), .. }
+        ));
+    }
+
+    const WINDOWS_CUE: &str =
+        "\u{feff}FILE \"game.bin\" BINARY \r\n  TRACK 01 MODE1/2352\t\r\n    INDEX 01 00:00:00  \r\n";
+
+    #[test]
+    fn bom_and_trailing_whitespace_are_ignored() {
+        let bin_files = parse(WINDOWS_CUE).unwrap();
+        assert_eq!(bin_files[0].cue_filename, "game.bin");
+        assert_eq!(bin_files[0].tracks[0].track_type, "MODE1/2352");
+        assert!(bin_files[0].metadata.is_empty());
+    }
+
+    #[test]
+    fn bom_is_ignored_by_rcue() {
+        let dir = TempDir::new("cue-bom");
+        let cue = dir.write("game.cue", WINDOWS_CUE);
+        let cd = get_cd_from_cue(&cue.to_string_lossy()).unwrap();
+        assert_eq!(cd.files[0].file, "game.bin");
+    }
+
+    const NO_INDEX_01: &str = concat!(
+        "FILE \"game.bin\" BINARY\n",
+        "  TRACK 01 MODE1/2352\n",
+        "    INDEX 01 00:00:00\n",
+        "  TRACK 02 AUDIO\n",
+        "    INDEX 00 00:02:00\n",
+    );
+
+    #[test]
+    fn track_without_index_01_is_refused() {
+        assert!(matches!(parse(NO_INDEX_01), Err(CueParseError::MissingIndex01 { track: 2, .. })));
+    }
+
+    #[test]
+    fn track_without_index_01_starts_at_its_index_00_when_lenient() {
+        let bin_files = parse_with(NO_INDEX_01, ParseOptions { lenient: true, ..ParseOptions::default() }).unwrap();
+        let track = &bin_files[0].tracks[1];
+        let indexes: Vec<(u32, u32)> = track.indexes.iter().map(|index| (index.id, index.file_offset)).collect();
+        assert_eq!(indexes, [(0, 150), (1, 150)]);
+        assert_eq!(track.file_offset, Some(150));
+    }
+
+    #[test]
+    fn cue_text_is_parsed_from_memory() {
+        let dir = TempDir::new("cue-from-memory");
+        dir.write("data.bin", vec![0; 10 * 2048]);
+        dir.write("audio.bin", vec![0; 300 * 2352]);
+        let cuesheet = concat!(
+            "FILE \"data.bin\" BINARY\n",
+            "  TRACK 01 MODE1/2048\n",
+            "    INDEX 01 00:00:00\n",
+            "FILE \"audio.bin\" BINARY\n",
+            "  TRACK 02 AUDIO\n",
+            "    INDEX 00 00:00:00\n",
+            "    INDEX 01 00:02:00\n",
+        );
+        let bin_files = parse_cue(Cursor::new(cuesheet), dir.path()).unwrap();
+
+        assert_eq!(bin_files.len(), 2);
+        assert_eq!(bin_files[0].filename, dir.path().join("data.bin"));
+        assert_eq!(bin_files[0].size, Some(10 * 2048));
+        let (data, audio) = (&bin_files[0].tracks[0], &bin_files[1].tracks[0]);
+        assert_eq!((data.num, data.track_type.as_str(), data.sector_size), (1, "MODE1/2048", 2048));
+        assert_eq!(data.sectors, Some(10));
+        assert_eq!((audio.num, audio.track_type.as_str(), audio.sector_size), (2, "AUDIO", 2352));
+        assert_eq!(audio.indexes.iter().map(|index| index.file_offset).collect::<Vec<_>>(), [0, 150]);
+        assert_eq!(audio.sectors, Some(300));
+    }
+
+    #[test]
+    fn frames_and_seconds_out_of_range_are_refused() {
+        assert_eq!(cuestamp_to_sectors("00:00:75"), Err("Frames must be below 75"));
+        assert_eq!(cuestamp_to_sectors("00:60:00"), Err("Seconds must be below 60"));
+        let err = parse("FILE \"game.bin\" BINARY\n  TRACK 01 AUDIO\n    INDEX 01 00:60:00\n").err().unwrap();
+        assert!(matches!(
+            err,
+            CueParseError::InvalidLine { line_number: 3, kind: CueLineError::InvalidTimestamp(_), .. }
+        ));
+    }
+
+    #[test]
+    fn frames_and_seconds_out_of_range_carry_over_when_lenient() {
+        let options = ParseOptions { lenient: true, ..ParseOptions::default() };
+        let cuesheet = concat!(
+            "FILE \"game.bin\" BINARY\n",
+            "  TRACK 01 AUDIO\n",
+            "    INDEX 01 00:00:75\n",
+            "  TRACK 02 AUDIO\n",
+            "    INDEX 01 00:60:00\n",
+        );
+        let bin_files = parse_with(cuesheet, options).unwrap();
+        let offsets: Vec<Option<u32>> = bin_files[0].tracks.iter().map(|track| track.file_offset).collect();
+        assert_eq!(offsets, [Some(75), Some(60 * 75)]);
+    }
+
+    #[test]
+    fn empty_bin_is_refused_unless_lenient() {
+        let dir = TempDir::new("cue-empty-bin");
+        dir.write("data.bin", [0; 2352]);
+        dir.write("empty.bin", []);
+        let cuesheet = concat!(
+            "FILE \"data.bin\" BINARY\n",
+            "  TRACK 01 MODE1/2352\n",
+            "    INDEX 01 00:00:00\n",
+            "FILE \"empty.bin\" BINARY\n",
+            "  TRACK 02 AUDIO\n",
+            "    INDEX 01 00:00:00\n",
+        );
+        let parse = |lenient| {
+            let options = ParseOptions { lenient, ..ParseOptions::default() };
+            parse_cue_lines(Cursor::new(cuesheet), dir.path(), &options).map(|(bin_files, _)| bin_files)
+        };
+        assert!(matches!(parse(false), Err(CueParseError::EmptyBinFile(path)) if path.ends_with("empty.bin")));
+        let bin_files = parse(true).unwrap();
+        assert_eq!(bin_files[1].tracks[0].sectors, Some(0));
+        assert_eq!(bin_files[1].sectors(), 0);
+    }
+
+    #[test]
+    fn lowercase_keywords_are_read_and_written_uppercase() {
+        let bin_files = parse(concat!(
+            "file \"game.bin\" binary\n",
+            "  track 01 mode1/2352\n",
+            "    index 01 00:00:00\n",
+            "  Track 02 Audio\n",
+            "    flags dcp\n",
+            "    pregap 00:02:00\n",
+            "    Index 01 00:01:00\n",
+        ))
+        .unwrap();
+        assert_eq!(bin_files[0].format, "BINARY");
+        assert_eq!(bin_files[0].tracks[0].track_type, "MODE1/2352");
+        assert_eq!(
+            render(&bin_files[0].tracks[1]),
+            "  TRACK 02 AUDIO\n    FLAGS DCP\n    PREGAP 00:02:00\n    INDEX 01 00:01:00\n"
+        );
+    }
+
+    #[test]
+    fn gap_in_track_numbers_is_reported() {
+        let cuesheet = concat!(
+            "FILE \"game.bin\" BINARY\n",
+            "  TRACK 01 MODE1/2352\n",
+            "    INDEX 01 00:00:00\n",
+            "  TRACK 02 AUDIO\n",
+            "    INDEX 01 00:01:00\n",
+            "  TRACK 04 AUDIO\n",
+            "    INDEX 01 00:02:00\n",
+        );
+        assert!(parse(cuesheet).is_ok());
+        let options = ParseOptions { strict_numbering: true, ..ParseOptions::default() };
+        let err = parse_with(cuesheet, options).err().unwrap();
+        assert!(matches!(err, CueParseError::TrackNumbering { expected: 3, found: 4 }));
+        assert_eq!(err.to_string(), "track 04 is listed where track 03 was expected");
+    }
+
+    #[test]
+    fn backslash_paths_are_resolved() {
+        let dir = TempDir::new("cue-backslash");
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        dir.write("sub/game.bin", [0; 2352]);
+        let cuesheet = "FILE \"sub\\game.bin\" BINARY\n  TRACK 01 MODE1/2352\n    INDEX 01 00:00:00\n";
+        let bin_files = parse_cue(Cursor::new(cuesheet), dir.path()).unwrap();
+        assert_eq!(bin_files[0].filename, dir.path().join("sub").join("game.bin"));
+        assert_eq!(bin_files[0].size, Some(2352));
+        assert_eq!(bin_files[0].cue_filename, "sub\\game.bin");
+    }
+
+    #[test]
+    fn tabs_and_runs_of_spaces_separate_tokens() {
+        let bin_files = parse(concat!(
+            "FILE\t\"game.bin\"\tBINARY\n",
+            "\tTRACK\t01\tMODE1/2352\n",
+            "\t\tINDEX  01   00:00:00\n",
+            "  TRACK  02 \t AUDIO\n",
+            "\t\tINDEX\t00\t00:00:10\n",
+            "    INDEX 01\t00:00:12\n",
+        ))
+        .unwrap();
+        assert_eq!(bin_files[0].cue_filename, "game.bin");
+        let tracks: Vec<(u32, &str)> =
+            bin_files[0].tracks.iter().map(|track| (track.num, track.track_type.as_str())).collect();
+        assert_eq!(tracks, [(1, "MODE1/2352"), (2, "AUDIO")]);
+        assert_eq!(bin_files[0].tracks[1].indexes.iter().map(|index| index.file_offset).collect::<Vec<_>>(), [10, 12]);
+    }
+
+    #[test]
+    fn track_numbers_past_99_keep_every_digit() {
+        let mut cuesheet = String::from("FILE \"game.bin\" BINARY\n");
+        for num in 1..=100 {
+            cuesheet += &format!("  TRACK {:02} AUDIO\n    INDEX 01 {}\n", num, sectors_to_cuestamp(num * 75));
+        }
+        let bin_files = parse(&cuesheet).unwrap();
+        assert_eq!(bin_files[0].tracks.len(), 100);
+        let (track_9, track_100) = (&bin_files[0].tracks[8], &bin_files[0].tracks[99]);
+        assert_eq!(track_100.num, 100);
+        assert_eq!(render(track_9), "  TRACK 09 AUDIO\n    INDEX 01 00:09:00\n");
+        assert_eq!(render(track_100), "  TRACK 100 AUDIO\n    INDEX 01 01:40:00\n");
+    }
+
+    #[test]
+    fn pregaps_after_the_start_of_a_bin_cant_be_moved() {
+        let mut bin_files = parse(concat!(
+            "FILE \"cd.bin\" BINARY\n",
+            "  TRACK 01 AUDIO\n",
+            "    INDEX 01 00:00:00\n",
+            "  TRACK 02 AUDIO\n",
+            "    PREGAP 00:02:00\n",
+            "    INDEX 00 00:10:00\n",
+            "    INDEX 01 00:12:00\n",
+        ))
+        .unwrap();
+        let sectors = bin_files[0].sectors();
+        assert!(matches!(bin_files[0].embed_pregap(), Err(CueParseError::Unmergeable(_))));
+        assert!(matches!(bin_files[0].strip_pregap(), Err(CueParseError::Unmergeable(_))));
+        assert_eq!(bin_files[0].sectors(), sectors);
+    }
+
+    #[test]
+    fn remap_replaces_a_drive_letter_path() {
+        let remap = [
+            ("C:\\cds".to_string(), "/srv/cds".to_string()),
+            ("D:\\games".to_string(), "/mnt/games".to_string()),
+        ];
+        // The first matching prefix wins, ignoring case like DOS
+        let path = bin_path(Path::new("cues"), "d:\\GAMES\\Game\\game.bin", &remap);
+        assert_eq!(path, Path::new("/mnt/games/Game/game.bin"));
+        assert_eq!(bin_path(Path::new("cues"), "E:\\game.bin", &remap), Path::new("cues").join("E:/game.bin"));
+    }
+
+    #[test]
+    fn track_types_are_split_into_mode_and_sector_size() {
+        assert_eq!(split_track_type("MODE1/2048"), ("MODE1", Some(2048)));
+        assert_eq!(split_track_type("MODE2/2352"), ("MODE2", Some(2352)));
+        assert_eq!(split_track_type("AUDIO"), ("AUDIO", None));
+        assert_eq!(split_track_type("MODE2/FORM1"), ("MODE2/FORM1", None));
+
+        assert_eq!(sector_size("MODE1/2048").unwrap(), 2048);
+        assert_eq!(sector_size("MODE2/2352").unwrap(), 2352);
+        assert_eq!(sector_size("AUDIO").unwrap(), 2352);
+        // A size the mode can't be stored in is as unknown as a mode that doesn't exist
+        for track_type in ["MODE3/2352", "MODE1/2336", "AUDIO/2352", "MODE1"] {
+            assert!(matches!(sector_size(track_type), Err(CueParseError::UnknownTrackType(t)) if t == track_type));
+        }
+    }
+
+    #[test]
+    fn tracks_keep_their_mode_and_sector_size() {
+        let bin_files = parse(concat!(
+            "FILE \"game.bin\" BINARY\n",
+            "  TRACK 01 MODE1/2048\n",
+            "    INDEX 01 00:00:00\n",
+            "FILE \"xa.bin\" BINARY\n",
+            "  TRACK 02 MODE2/2352\n",
+            "    INDEX 01 00:00:00\n",
+            "FILE \"audio.bin\" BINARY\n",
+            "  TRACK 03 AUDIO\n",
+            "    INDEX 01 00:00:00\n",
+        ))
+        .unwrap();
+        let tracks: Vec<(&str, &str, u32)> = bin_files
+            .iter()
+            .flat_map(|bin_file| &bin_file.tracks)
+            .map(|track| (track.track_type.as_str(), track.mode.as_str(), track.sector_size))
+            .collect();
+        assert_eq!(tracks, [("MODE1/2048", "MODE1", 2048), ("MODE2/2352", "MODE2", 2352), ("AUDIO", "AUDIO", 2352)]);
+
+        let unknown = parse("FILE \"game.bin\" BINARY\n  TRACK 01 MODE3/2352\n    INDEX 01 00:00:00\n");
+        assert!(matches!(unknown, Err(CueParseError::UnknownTrackType(track_type)) if track_type == "MODE3/2352"));
+    }
+
+    #[test]
+    fn bins_can_have_any_extension() {
+        let dir = TempDir::new("cue-img");
+        dir.write("game.img", vec![0; 4 * 2048]);
+        dir.write("game.iso.part2", vec![0; 3 * 2352]);
+        let cuesheet = concat!(
+            "FILE \"game.img\" BINARY\n",
+            "  TRACK 01 MODE1/2048\n",
+            "    INDEX 01 00:00:00\n",
+            "FILE \"game.iso.part2\" BINARY\n",
+            "  TRACK 02 AUDIO\n",
+            "    INDEX 01 00:00:00\n",
+        );
+        let cue = dir.write("game.cue", cuesheet);
+        let bin_files = get_bin_from_cue(&cue.to_string_lossy()).unwrap();
+
+        assert_eq!(bin_files[0].filename, dir.path().join("game.img"));
+        assert_eq!(bin_files[0].size, Some(4 * 2048));
+        assert_eq!(bin_files[0].tracks[0].sectors, Some(4));
+        assert_eq!(bin_files[1].filename, dir.path().join("game.iso.part2"));
+        assert_eq!(bin_files[1].tracks[0].sectors, Some(3));
+    }
+}
@@ -0,0 +1,201 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{self, Component, Path, PathBuf};
+use log::{error, warn};
+
+/// Appends the name of every file in `dir` to `file_list`.
+///
+/// A name that isn't valid UTF-8 can't be stored as a `String`, so that file is skipped with a warning, see [`files`]
+/// for the paths of every file.
+pub fn read_directory(file_list: &mut Vec<String>, dir: &Path) -> io::Result<bool> {
+    match fs::read_dir(dir) {
+        Err(e) => error!("There was an error reading the directory: {}", e),
+        Ok(paths) => {
+            for path in paths {
+                match path {
+                    Err(e) => warn!("There was an error with one of the entries: {}", e),
+                    Ok(p) => if p.path().is_file() {
+                        match p.file_name().into_string() {
+                            Ok(file_name) => file_list.push(file_name),
+                            Err(file_name) => {
+                                warn!("Skipping {}, its name isn't valid UTF-8", file_name.to_string_lossy())
+                            }
+                        }
+                    }
+                }
+            }
+        },
+    }
+    Ok(true)
+}
+
+/// Lists the paths of every file in `dir`.
+pub fn files(dir: &Path) -> Result<Vec<PathBuf>, io::Error> {
+    Ok(fs::read_dir(dir)?
+        .filter(|r| r.is_ok()) // Get rid of Err variants for Result<DirEntry>
+        .map(|r| r.unwrap().path()) // This is safe, since we only have the Ok variants
+        .filter(|r| r.is_file()) // Filter out non-files
+        .collect())
+}
+
+/// Lists the paths of every file in `dir` and its subdirectories, down to `max_depth` levels below `dir` when set,
+/// with `Some(0)` being [`files`] sorted. The paths are sorted.
+///
+/// Symlinked directories are followed, except back into a directory already listed, so a link up the tree can't
+/// loop. A subdirectory or entry that can't be read, e.g. for lack of permission, is skipped with a warning.
+pub fn files_recursive(dir: &Path, max_depth: Option<usize>) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut visited = HashSet::new();
+    visited.insert(fs::canonicalize(dir)?);
+    let mut dirs = vec![(fs::read_dir(dir)?, 0)];
+    while let Some((entries, depth)) = dirs.pop() {
+        for entry in entries {
+            let path = match entry {
+                Ok(entry) => entry.path(),
+                Err(e) => {
+                    warn!("There was an error with one of the entries: {}", e);
+                    continue;
+                }
+            };
+            if path.is_file() {
+                files.push(path);
+            } else if path.is_dir() && max_depth.is_none_or(|max_depth| depth < max_depth) {
+                match fs::canonicalize(&path).map(|canonical| visited.insert(canonical)) {
+                    Ok(false) => warn!("Skipping {}, it links back to a directory already listed", path.display()),
+                    Ok(true) => match fs::read_dir(&path) {
+                        Ok(entries) => dirs.push((entries, depth + 1)),
+                        Err(e) => warn!("Skipping {}: {}", path.display(), e),
+                    },
+                    Err(e) => warn!("Skipping {}: {}", path.display(), e),
+                }
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Reads the bin paths listed in the file at `list_path`, one per line, resolved against the list's directory.
+///
+/// Blank lines are skipped. Fails on the first listed path that isn't an existing file.
+pub fn read_input_list(list_path: &Path) -> io::Result<Vec<PathBuf>> {
+    let list_dir = list_path.parent().unwrap_or(Path::new("."));
+    let mut bins = Vec::new();
+    for (line_index, line) in fs::read_to_string(list_path)?.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let bin = list_dir.join(line);
+        if !bin.is_file() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("line {} of {}: {} isn't a file", line_index + 1, list_path.display(), bin.display()),
+            ));
+        }
+        bins.push(bin);
+    }
+    Ok(bins)
+}
+
+/// Creates the output directory `outdir` and any missing parents. Fails if `outdir` exists but isn't a directory.
+pub fn create_outdir(outdir: &Path) -> io::Result<()> {
+    check_outdir(outdir)?;
+    fs::create_dir_all(outdir)
+}
+
+/// Fails if `outdir` exists but isn't a directory, without creating anything.
+pub fn check_outdir(outdir: &Path) -> io::Result<()> {
+    if outdir.exists() && !outdir.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotADirectory,
+            format!("Output directory is not a directory: {}", outdir.display()),
+        ));
+    }
+    Ok(())
+}
+
+/// The path of a file named `filename` directly in `outdir`.
+///
+/// Fails when `filename` isn't a single plain path component, such as a name with a separator or `..` that would
+/// put the file somewhere else.
+pub fn output_path(outdir: &Path, filename: &str) -> io::Result<PathBuf> {
+    let mut components = Path::new(filename).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(name)), None) if name == filename && !filename.contains(['/', '\\']) => {
+            Ok(outdir.join(filename))
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Output filename must not be a path: {}", filename),
+        )),
+    }
+}
+
+/// The path of `path` relative to the directory `base`, going up with `..` where they part ways. Both are made
+/// absolute first.
+///
+/// Paths without a common root, such as on different Windows drives, can't be relative, so `path` is returned
+/// absolute instead.
+pub fn relative_path(base: &Path, path: &Path) -> io::Result<PathBuf> {
+    let base = path::absolute(if base.as_os_str().is_empty() { Path::new(".") } else { base })?;
+    let path = path::absolute(path)?;
+    let common = base.components().zip(path.components()).take_while(|(a, b)| a == b).count();
+    if common == 0 {
+        return Ok(path);
+    }
+
+    let mut relative = PathBuf::new();
+    for _ in base.components().skip(common) {
+        relative.push("..");
+    }
+    relative.extend(path.components().skip(common));
+    Ok(relative)
+}
+
+/// Makes `name` safe to use as a filename on FAT and NTFS, shared by most handhelds and SD cards.
+///
+/// Characters those filesystems reject (`\ / : * ? " < > |` and control characters) become `_`, and trailing dots
+/// and spaces, which Windows drops, are removed.
+pub fn sanitize_filename(name: &str) -> String {
+    let reserved = |c: char| matches!(c, '\\' | '/' | ':' | '*' | '?' | '"' | '<' | '>' | '|') || c.is_control();
+    let sanitized: String = name.chars().map(|c| if reserved(c) { '_' } else { c }).collect();
+    sanitized.trim_end_matches(['.', ' ']).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    // Linux filesystems take any bytes in a name, while macOS and Windows ones only take valid Unicode
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn name_that_isnt_utf8_is_skipped_or_kept_as_a_path() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = TempDir::new("dir-not-utf8");
+        dir.write("good.bin", []);
+        let bad = dir.path().join(OsStr::from_bytes(b"bad\xff.bin"));
+        fs::write(&bad, []).unwrap();
+
+        let mut names = Vec::new();
+        assert!(read_directory(&mut names, dir.path()).unwrap());
+        assert_eq!(names, ["good.bin"]);
+        assert_eq!(files_recursive(dir.path(), None).unwrap(), [bad, dir.path().join("good.bin")]);
+    }
+
+    #[test]
+    fn output_path_stays_in_the_output_directory() {
+        let outdir = Path::new("out");
+        assert_eq!(output_path(outdir, "game (Track 01).bin").unwrap(), outdir.join("game (Track 01).bin"));
+        assert_eq!(output_path(outdir, "..game.bin").unwrap(), outdir.join("..game.bin"));
+        let paths = ["../../x.bin", "..", ".", "/tmp/x.bin", "sub/x.bin", "sub\\x.bin", "C:\\x.bin", "./x.bin", ""];
+        for filename in paths {
+            let err = output_path(outdir, filename).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{}", filename);
+        }
+    }
+}
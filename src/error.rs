@@ -0,0 +1,63 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+use crate::cue::CueParseError;
+
+/// Error returned by the operations of this crate that parse cue sheets or merge and split bins, so there's a single
+/// type to match on. Smaller helpers, such as [`BinFile::new`](crate::BinFile::new) or
+/// [`sector_size`](crate::sector_size), return the [`io::Error`] or [`CueParseError`] they can fail with, which
+/// convert into it with `?`.
+#[derive(Debug)]
+pub enum BinmergeError {
+    /// A file couldn't be read or written
+    Io(io::Error),
+    /// A cue sheet couldn't be read or parsed, or describes bins that can't be used
+    Parse(CueParseError),
+    /// The arguments or files don't allow the operation, and why
+    Validation(String),
+}
+
+impl fmt::Display for BinmergeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BinmergeError::Io(err) => write!(f, "{}", err),
+            BinmergeError::Parse(err) => write!(f, "{}", err),
+            BinmergeError::Validation(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl error::Error for BinmergeError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            BinmergeError::Io(err) => Some(err),
+            BinmergeError::Parse(err) => Some(err),
+            BinmergeError::Validation(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for BinmergeError {
+    fn from(err: io::Error) -> Self {
+        BinmergeError::Io(err)
+    }
+}
+
+impl From<CueParseError> for BinmergeError {
+    fn from(err: CueParseError) -> Self {
+        BinmergeError::Parse(err)
+    }
+}
+
+impl From<String> for BinmergeError {
+    fn from(reason: String) -> Self {
+        BinmergeError::Validation(reason)
+    }
+}
+
+impl From<&str> for BinmergeError {
+    fn from(reason: &str) -> Self {
+        BinmergeError::Validation(reason.to_string())
+    }
+}
@@ -0,0 +1,51 @@
+//! Merge the bins of a multi-bin cue sheet into a single bin, or split a single bin back into one bin per track.
+//!
+//! [`get_bin_from_cue`] parses a cue sheet into the [`BinFile`]s it references, which [`merge_files`] and
+//! [`write_merged_cue`] turn into a single bin and cue sheet, while [`split_bin`] does the reverse.
+//! These and the other operations that parse cue sheets or merge and split bins fail with a [`BinmergeError`].
+//!
+//! Warnings and timings go through the [`log`] crate, at the `warn` and `debug` levels, for whichever logger the
+//! application installs.
+
+mod checksum;
+mod cue;
+mod dir;
+mod error;
+mod merge;
+mod normalize;
+mod reindex;
+mod split;
+#[cfg(test)]
+mod testing;
+#[cfg(feature = "toc")]
+mod toc;
+mod verify;
+mod wav;
+
+pub use checksum::{Checksum, Checksums, Hasher};
+pub use cue::{
+    cross_check_cue, cuestamp_to_sectors, disc_mode, get_bin_from_cue, get_bin_from_cue_in, get_bin_from_cue_with,
+    get_bin_from_cue_zero_filled, get_cd_from_cue, is_audio_track, parse_cue, print_bin_files, print_cd_summary,
+    sector_size, sectors_to_cuestamp, split_track_type, total_length, total_sectors, BinFile, CueLineError,
+    CueParseError, DiscMode, Index, LineEnding, ParseOptions, Track,
+};
+pub use dir::{
+    check_outdir, create_outdir, files, files_recursive, output_path, read_directory, read_input_list, relative_path,
+    sanitize_filename,
+};
+pub use error::BinmergeError;
+pub use merge::{
+    append_files, merge_files, merge_files_with, merged_format, merge_to_writer, partial_path, remove_partial_bins,
+    render_merged_cue, write_checksum_sidecar, write_m3u, write_merged_cue, write_via_partial, MergeInput, MergeOptions,
+    TrackHashers, DEFAULT_BUFFER_SIZE,
+};
+pub use normalize::normalize_cue;
+pub use reindex::reindex;
+pub use split::{
+    parse_track_list, read_track, split_bin, split_bin_named, split_bin_with, write_track, Trailing,
+    DEFAULT_TRACK_TEMPLATE,
+};
+#[cfg(feature = "toc")]
+pub use toc::{get_bin_from_toc, is_toc};
+pub use verify::{print_gap_report, print_sector_report, print_track_list, verify_bin, verify_bin_json};
+pub use wav::{export_wav, wav_header, WAV_HEADER_SIZE};
@@ -1,351 +1,1108 @@
-use std::error;
-use std::fs::{self, File, OpenOptions};
-use std::io::{self, Read, Write, BufRead};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io;
 use std::path::{Path, PathBuf};
-use std::time::{Duration, Instant};
-use lazy_static::lazy_static;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use log::{error, warn, Level, LevelFilter, Log, Metadata, Record};
 
-use rcue::parser::parse_from_file;
-use rcue::parser::parse;
+use binmerge_rs::{
+    append_files, check_outdir, create_outdir, export_wav, files_recursive, get_bin_from_cue_with, is_audio_track,
+    merge_files_with, merged_format, merge_to_writer, normalize_cue, output_path, parse_track_list, print_bin_files,
+    print_gap_report, print_sector_report, print_track_list, read_input_list, reindex, remove_partial_bins,
+    render_merged_cue, sanitize_filename, sector_size, split_bin_with, total_length, total_sectors, verify_bin,
+    verify_bin_json, write_checksum_sidecar, write_m3u, write_merged_cue, write_via_partial, BinFile, BinmergeError,
+    Checksum, DiscMode, Index, LineEnding, MergeInput, ParseOptions, Trailing, Track, TrackHashers, DEFAULT_BUFFER_SIZE,
+    DEFAULT_TRACK_TEMPLATE,
+};
 
-use regex::Regex;
-
-lazy_static! {
-    static ref FILE_PATTERN: Regex = Regex::new(r#"FILE "(.*?)" BINARY"#).unwrap();
-    static ref TRACK_PATTERN: Regex = Regex::new(r#"TRACK (\d+) ([^\s]*)"#).unwrap();
-    static ref INDEX_PATTERN: Regex = Regex::new(r#"INDEX (\d+) (\d+:\d+:\d+)"#).unwrap();
-    static ref CUESTAMP_PATTERN: Regex = Regex::new(r"(\d+):(\d+):(\d+)").unwrap();
+// Levels of RUST_LOG the way env_logger reads them: a comma separated list of a level for every module, and of
+// module=level, or a module alone for all of its records, for a module and the ones inside it
+struct LogFilter {
+    default: LevelFilter,
+    modules: Vec<(String, LevelFilter)>,
 }
 
-struct Index {
-    id: u32,
-    stamp: String,
-    file_offset: u32, // Assuming cuestamp_to_sectors returns an i32
+impl LogFilter {
+    // The filter, along with the directives it can't read
+    fn parse(spec: &str, default: LevelFilter) -> (LogFilter, Vec<String>) {
+        let mut filter = LogFilter { default, modules: Vec::new() };
+        let mut invalid = Vec::new();
+        for directive in spec.split(',').map(str::trim).filter(|directive| !directive.is_empty()) {
+            let (module, level) = match directive.split_once('=') {
+                Some((module, level)) => (Some(module.trim()), level.trim().parse().ok()),
+                None => match directive.parse() {
+                    Ok(level) => (None, Some(level)),
+                    Err(_) => (Some(directive), Some(LevelFilter::Trace)),
+                },
+            };
+            // env_logger's /regex message filters aren't supported
+            match (module, level) {
+                (None, Some(level)) => filter.default = level,
+                (Some(module), Some(level)) if !module.is_empty() && !module.contains(['/', ' ']) => {
+                    filter.modules.push((module.to_string(), level))
+                }
+                _ => invalid.push(directive.to_string()),
+            }
+        }
+        (filter, invalid)
+    }
+
+    // Level of the most specific module that target is in, the later one for a module listed twice
+    fn level(&self, target: &str) -> LevelFilter {
+        let in_module = |module: &str| {
+            target.strip_prefix(module).is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+        };
+        let modules = self.modules.iter().filter(|(module, _)| in_module(module));
+        modules.max_by_key(|(module, _)| module.len()).map_or(self.default, |(_, level)| *level)
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        self.modules.iter().map(|(_, level)| *level).fold(self.default, Ord::max)
+    }
 }
 
-impl Index {
-    fn new(id: u32, stamp: String, file_offset: u32) -> Index {
-        Index {
-            id,
-            stamp,
-            file_offset,
+// Prints log records to stderr the way the CLI always printed its errors and warnings
+struct StderrLogger(LogFilter);
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.0.level(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            match record.level() {
+                Level::Error => eprintln!("Error: {}", record.args()),
+                Level::Warn => eprintln!("Warning: {}", record.args()),
+                _ => eprintln!("{}", record.args()),
+            }
         }
     }
+
+    fn flush(&self) {}
 }
 
-struct Track {
-    num: u32,
-    indexes: Vec<Index>,
-    track_type: String,
-    sectors: Option<u32>,
-    file_offset: Option<u32>,
+// Warnings and errors by default, timings with --verbose, or whatever RUST_LOG asks for
+fn init_logger(verbose: bool) {
+    let spec = std::env::var("RUST_LOG").unwrap_or_default();
+    let (mut filter, invalid) = LogFilter::parse(&spec, LevelFilter::Warn);
+    if verbose {
+        filter.default = filter.default.max(LevelFilter::Debug);
+    }
+    log::set_max_level(filter.max_level());
+    log::set_logger(Box::leak(Box::new(StderrLogger(filter)))).expect("the logger is only set once");
+    for directive in invalid {
+        warn!("Ignoring the RUST_LOG directive {}, expected a level, a module or module=level", directive);
+    }
 }
 
-impl Track {
-    fn new(num: u32, track_type: String) -> Track {
-        Track {
-            num,
-            indexes: Vec::new(),
-            track_type,
-            sectors: None,
-            file_offset: None,
+// Ctrl-C, and the SIGTERM of `kill` or a service manager, remove the partial bins of the merges still running and exit
+// with the status a shell gives a process stopped by Ctrl-C. The signal handler only wakes a thread that does that,
+// since removing files isn't safe to do in a signal handler
+#[cfg(unix)]
+fn remove_partial_bins_on_interrupt() -> io::Result<()> {
+    use std::io::Read;
+    use std::os::raw::c_int;
+    use std::os::unix::io::IntoRawFd;
+    use std::os::unix::net::UnixStream;
+    use std::sync::atomic::AtomicI32;
+
+    const SIGINT: c_int = 2;
+    const SIGTERM: c_int = 15;
+    const SIG_ERR: usize = usize::MAX;
+    static WAKE_FD: AtomicI32 = AtomicI32::new(-1);
+
+    extern "C" {
+        fn signal(signum: c_int, handler: extern "C" fn(c_int)) -> usize;
+        fn write(fd: c_int, buf: *const u8, count: usize) -> isize;
+    }
+    extern "C" fn on_signal(_: c_int) {
+        // SAFETY: write is async-signal-safe, and the descriptor is never closed
+        unsafe { write(WAKE_FD.load(Ordering::Relaxed), [0u8].as_ptr(), 1) };
+    }
+
+    let (mut wait, wake) = UnixStream::pair()?;
+    WAKE_FD.store(wake.into_raw_fd(), Ordering::Relaxed);
+    thread::spawn(move || {
+        if wait.read(&mut [0]).is_ok() {
+            remove_partial_bins();
+            process::exit(130);
+        }
+    });
+    for signum in [SIGINT, SIGTERM] {
+        // SAFETY: on_signal only calls write, on a descriptor set up before any signal can reach it
+        if unsafe { signal(signum, on_signal) } == SIG_ERR {
+            return Err(io::Error::last_os_error());
         }
     }
+    Ok(())
 }
 
-struct BinFile {
-    filename: String,
-    tracks: Vec<Track>,
-    size: Option<u64>,
+// Windows runs the handler on a thread of its own for every Ctrl-C, Ctrl-Break and closed console
+#[cfg(windows)]
+fn remove_partial_bins_on_interrupt() -> io::Result<()> {
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn SetConsoleCtrlHandler(handler: Option<unsafe extern "system" fn(u32) -> i32>, add: i32) -> i32;
+    }
+    unsafe extern "system" fn on_ctrl(_: u32) -> i32 {
+        remove_partial_bins();
+        process::exit(130)
+    }
+
+    // SAFETY: on_ctrl is a handler with the signature SetConsoleCtrlHandler expects
+    if unsafe { SetConsoleCtrlHandler(Some(on_ctrl), 1) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
 }
 
-impl BinFile {
-    fn new(filepath: PathBuf) -> io::Result<BinFile> {
-        let size = fs::metadata(&filepath)?.len(); // Performance hit
+#[cfg(not(any(unix, windows)))]
+fn remove_partial_bins_on_interrupt() -> io::Result<()> {
+    Ok(())
+}
 
-        Ok(BinFile {
-            filename: filepath.to_str().unwrap().to_string(),
-            tracks: Vec::new(),
-            size: Some(size),
-        })
+// Prints the merge progress on a single stderr line, only when the percentage changes
+fn progress_printer() -> impl FnMut(u64, u64) {
+    let mut last_percent = None;
+    move |bytes_written, total| {
+        let percent = (bytes_written * 100).checked_div(total).unwrap_or(100);
+        if last_percent != Some(percent) {
+            last_percent = Some(percent);
+            eprint!("\rMerging... {}%", percent);
+            if bytes_written == total {
+                eprintln!();
+            }
+        }
     }
 }
 
-fn cuestamp_to_sectors(timestamp: &str) -> Result<u32, &'static str> {
-    let start_cuestamp = Instant::now();
+// What merge did with a cue sheet
+enum MergeOutcome {
+    // The merged cue sheet written
+    Merged(PathBuf),
+    // The cue sheet already references a single bin
+    Skipped,
+}
 
-    let duration_cuestamp = start_cuestamp.elapsed();
+fn merge(
+    input: &str,
+    basedir: Option<&Path>,
+    outdir: &Path,
+    name: &str,
+    args: &MergeOptions,
+    verbose: bool,
+) -> Result<MergeOutcome, BinmergeError> {
+    let parse_options = ParseOptions {
+        basedir,
+        zero_fill_missing: args.zero_fill_missing,
+        lenient: args.lenient,
+        strict_numbering: args.strict_numbering,
+        strict_sizes: args.strict_sizes,
+        remap: &args.remap,
+    };
+    let (mut bin_files, zero_filled) = get_bin_from_cue_with(input, &parse_options)?;
+    if !zero_filled.is_empty() {
+        let mut message = "THESE BINS ARE MISSING AND WILL BE ZERO FILLED, the merged image is incomplete:".to_string();
+        for bin_file in bin_files.iter().filter(|bin_file| zero_filled.contains(&bin_file.filename)) {
+            message += &format!("\n  {} ({} bytes of zeros)", bin_file.filename.display(), bin_file.size.unwrap_or(0));
+        }
+        warn!("{}", message);
+    }
+    if verbose {
+        print_bin_files(&bin_files);
+    }
 
-    if let Some(caps) = CUESTAMP_PATTERN.captures(&timestamp) {
-        let minutes = caps.get(1).ok_or("Invalid timestamp")?.as_str().parse::<u32>().map_err(|_| "Invalid minutes")?;
-        let seconds = caps.get(2).ok_or("Invalid timestamp")?.as_str().parse::<u32>().map_err(|_| "Invalid seconds")?;
-        let frames = caps.get(3).ok_or("Invalid timestamp")?.as_str().parse::<u32>().map_err(|_| "Invalid frames")?;
-        
-        println!("Time elapsed in cuestamp_to_sectors() is: {:?}", duration_cuestamp);
-        Ok(frames + (seconds * 75) + (minutes * 60 * 75))
-    } else {
-        Err("Timestamp does not match pattern")
+    if bin_files.len() == 1 && !args.force && !args.append {
+        println!("Already a single bin, nothing to merge");
+        return Ok(MergeOutcome::Skipped);
+    }
+    merged_format(&bin_files)?;
+
+    let track_types: Vec<&str> = bin_files.iter()
+        .flat_map(|bin_file| &bin_file.tracks)
+        .map(|track| track.track_type.as_str())
+        .collect();
+    println!("Disc mode: {}", DiscMode::from_track_types(track_types.iter().copied()));
+    // Mixed mode discs put their data track first, a data track after audio is something many emulators don't expect
+    if let Some(pair) = track_types.windows(2).find(|pair| is_audio_track(pair[0]) && !is_audio_track(pair[1])) {
+        warn!("{} has a {} track after an {} track, some tools may not read it", input, pair[1], pair[0]);
     }
-}
 
-fn print_bin_files(bin_files: &Vec<BinFile>) {
-    for bin_file in bin_files{
-        println!("-- File --");
-        println!("Filename: {}", bin_file.filename);
-        println!("Size: {} bytes", bin_file.size.unwrap_or(0));
-        println!("Tracks: {}", bin_file.tracks.len());
+    let name = match &args.rename {
+        Some(template) => {
+            let title = bin_files.first().and_then(BinFile::title).unwrap_or(name);
+            sanitize_filename(&template.replace("{name}", name).replace("{title}", title))
+        }
+        None => name.to_string(),
+    };
+    if name.is_empty() {
+        return Err("Merged name is empty".into());
+    }
+    // The merged bin is only ever concatenated bytes, so any extension will do as long as it isn't the cue's
+    let ext = sanitize_filename(args.ext.trim_start_matches('.'));
+    if ext.is_empty() || ext.eq_ignore_ascii_case("cue") {
+        return Err(format!("Invalid extension for the merged bin: {}", args.ext).into());
+    }
+    let merged_name = format!("{}.{}", name, ext);
+    let merged_bin = output_path(outdir, &merged_name)?;
+    let merged_cue = output_path(outdir, &format!("{}.cue", name))?;
 
-        for track in &bin_file.tracks {
-            println!("-- Track --");
-            println!("Track number: {}", track.num);
-            println!("Track type: {}", track.track_type);
-            println!("Track indexes: {}", track.indexes.len());
+    // The merged image being appended to comes first, standing in for all the bins merged into it before
+    let appended_to = if args.append {
+        bin_files.insert(0, merged_image(&merged_bin, &merged_cue)?);
+        merged_format(&bin_files)?;
+        renumber_appended_tracks(&mut bin_files, args.first_track)?;
+        1
+    } else {
+        0
+    };
 
-            for index in &track.indexes {
-                println!("-- Index --");
-                println!("Index id: {}", index.id);
-                println!("Index stamp: {}", index.stamp);
-                println!("Index file offset: {}", index.file_offset);
+    // Refuse before writing the bin rather than leave a bin behind without its cue
+    let outputs = match (args.no_cue, args.cue_only) {
+        (true, _) => vec![&merged_bin],
+        (_, true) => vec![&merged_cue],
+        _ => vec![&merged_bin, &merged_cue],
+    };
+    if !args.overwrite && !args.append {
+        for path in outputs {
+            if path.exists() {
+                return Err(format!("Target merged path already exists: {}", path.display()).into());
             }
         }
     }
-}
 
-fn get_bin_from_cue(cue_path : &str) -> io::Result<Vec<BinFile>> {
-    let mut bin_files: Vec<BinFile> = Vec::new();
+    // Every index moves along with the pregap, and zeros written or bytes cut at the start of a bin keep the merged
+    // bin in step with them
+    let mut pregaps = Vec::new();
+    for bin_file in &mut bin_files[appended_to..] {
+        let (embedded, stripped) = match args.pregap_mode {
+            PregapMode::Preserve => (0, 0),
+            PregapMode::Embed => (bin_file.embed_pregap()?, 0),
+            PregapMode::Strip => (0, bin_file.strip_pregap()?),
+        };
+        if embedded > 0 {
+            println!("Embedding {} bytes of pregap before {}", embedded, bin_file.filename.display());
+        }
+        if stripped > 0 {
+            println!("Stripping {} bytes of pregap from {}", stripped, bin_file.filename.display());
+        }
+        pregaps.push((embedded, stripped));
+    }
 
-    let mut missing_bin_file = false;
+    // Only padded when asked, so the merged bin otherwise stays byte for byte the same as the inputs
+    let mut padding = Vec::new();
+    for bin_file in &mut bin_files[appended_to..] {
+        if args.pad_to_sector {
+            let padded = bin_file.pad_to_sector();
+            if padded > 0 {
+                println!("Padding {} with {} bytes", bin_file.filename.display(), padded);
+            }
+            padding.push(padded);
+        } else {
+            if bin_file.sector_padding() > 0 {
+                warn!("{} is not a whole number of sectors, use --pad-to-sector to pad it", bin_file.filename.display());
+            }
+            padding.push(0);
+        }
+    }
 
-    let cue_file = File::open(cue_path)?;
-    let reader = io::BufReader::new(cue_file);
+    // A CRC-32 noted by an earlier --annotate-crc no longer holds for a track whose bytes the merge changes
+    for ((bin_file, (embedded, stripped)), padded) in bin_files[appended_to..].iter_mut().zip(&pregaps).zip(&padding) {
+        let zero_filled = zero_filled.contains(&bin_file.filename);
+        let last = bin_file.tracks.len().saturating_sub(1);
+        for (i, track) in bin_file.tracks.iter_mut().enumerate() {
+            if zero_filled || (i == 0 && embedded + stripped > 0) || (i == last && *padded > 0) {
+                track.metadata.retain(|command| !is_crc_comment(command));
+            }
+        }
+    }
 
-    let start = Instant::now();
+    // The cue sheet is only right for a merged bin made of exactly these bins, which its size is the best check for
+    let merged_size: u64 = bin_files.iter().map(|bin_file| bin_file.size.unwrap_or(0)).sum();
+    if let Some(max_size) = args.max_size.filter(|max_size| merged_size > *max_size && !args.cue_only) {
+        return Err(format!(
+            "The merged bin would be {} bytes, more than the --max-size of {} bytes",
+            merged_size,
+            max_size
+        )
+        .into());
+    }
+    if args.cue_only {
+        let size = match fs::metadata(&merged_bin) {
+            Ok(metadata) => metadata.len(),
+            Err(e) => return Err(format!("Cannot read the merged bin {}: {}", merged_bin.display(), e).into()),
+        };
+        if size != merged_size {
+            return Err(format!(
+                "{} is {} bytes but the bins of {} add up to {} bytes",
+                merged_bin.display(),
+                size,
+                input,
+                merged_size
+            )
+            .into());
+        }
+    }
 
-    let mut current_file_index: Option<usize> = None;
-    let mut current_track_index: Option<usize> = None;
-    let mut current_index_index : Option<usize> = None;
-    
-    for line in reader.lines() {
-        let line = line?;
+    check_outdir(outdir)?;
+    if args.dry_run {
+        println!("Input bins:");
+        for bin_file in &bin_files[appended_to..] {
+            println!("  {} ({} bytes)", bin_file.filename.display(), bin_file.size.unwrap_or(0));
+        }
+        println!(
+            "Merged bin: {} ({} bytes, {} sectors, {})",
+            merged_bin.display(),
+            merged_size,
+            total_sectors(&bin_files),
+            total_length(&bin_files)
+        );
+        if !args.no_cue {
+            println!("Merged cue: {}", merged_cue.display());
+            print!("{}", render_merged_cue(&bin_files, &merged_name)?);
+        }
+        return Ok(MergeOutcome::Merged(merged_cue));
+    }
 
-        // Process file lines
-        if let Some(caps) = FILE_PATTERN.captures(&line) {
-            let start_bin_file = Instant::now();
-            
-            if let Some(bin) = caps.get(1) {
-                let bin_file_path = Path::new(cue_path).parent().unwrap().join(bin.as_str());
-                //let bin_file = File::open(bin_file_path);
-                //println!("Bin file: {}", bin_file_path.to_str().unwrap());
-                let current_bin_file = BinFile::new(bin_file_path).unwrap();
-                bin_files.push(current_bin_file);
-                current_file_index = Some(bin_files.len() - 1);
-                current_track_index = None;
-                current_index_index = None;
+    create_outdir(outdir)?;
+    if !args.cue_only {
+        let track_crcs = merge_bins(args, &bin_files[appended_to..], &zero_filled, &pregaps, padding, &merged_bin)?;
+        // The CRC-32 of an earlier merge is replaced, the track may not be the same bytes any more
+        let tracks = bin_files[appended_to..].iter_mut().flat_map(|bin_file| &mut bin_file.tracks);
+        for (track, crc) in tracks.zip(track_crcs.unwrap_or_default()) {
+            track.metadata.retain(|command| !is_crc_comment(command));
+            track.metadata.push(format!("REM CRC-32 {}", crc));
+        }
+    }
 
-                let duration_bin_file = start_bin_file.elapsed();
-                println!("Time elapsed in BinFile::new() is: {:?}", duration_bin_file);
+    if !args.no_cue {
+        let line_ending = match args.line_endings {
+            LineEndings::Auto => LineEnding::detect(input)?,
+            LineEndings::Crlf => LineEnding::Crlf,
+            LineEndings::Lf => LineEnding::Lf,
+        };
+        write_merged_cue(&bin_files, &merged_cue, &merged_name, args.overwrite || args.append, line_ending)?;
+    }
 
-                continue;
+    // The merge has already checked that the merged bin adds up to the input bins by now
+    if args.remove_source {
+        remove_sources(input, &bin_files[appended_to..], &[&merged_bin, &merged_cue])?;
+    }
+    Ok(MergeOutcome::Merged(merged_cue))
+}
+
+// A REM CRC-32 line written by --annotate-crc
+fn is_crc_comment(command: &str) -> bool {
+    command.to_ascii_uppercase().starts_with("REM CRC-32 ")
+}
+
+// Writes the merged bin, or appends to it with --append, printing the checksums asked for. Returns the CRC32 of every
+// track with --annotate-crc
+fn merge_bins(
+    args: &MergeOptions,
+    bin_files: &[BinFile],
+    zero_filled: &[PathBuf],
+    pregaps: &[(u64, u64)],
+    padding: Vec<u64>,
+    merged_bin: &Path,
+) -> Result<Option<Vec<String>>, BinmergeError> {
+    // A missing bin is written as zeros standing in for its whole size, embedded pregap included, and an embedded
+    // pregap as zeros before its bin
+    let mut files = Vec::new();
+    for ((bin_file, (embedded, stripped)), padding) in bin_files.iter().zip(pregaps).zip(padding) {
+        if zero_filled.contains(&bin_file.filename) {
+            files.push(MergeInput { path: None, skip: 0, padding: bin_file.size.unwrap_or(0) });
+            continue;
+        }
+        if *embedded > 0 {
+            files.push(MergeInput { path: None, skip: 0, padding: *embedded });
+        }
+        files.push(MergeInput { path: Some(bin_file.filename.as_path()), skip: *stripped, padding });
+    }
+    let mut track_hashers = args.annotate_crc.then(|| TrackHashers::new(bin_files, Checksum::Crc32)).transpose()?;
+    let mut on_progress = args.progress.then(progress_printer);
+    let mut bytes_written = 0;
+    let mut on_chunk = |chunk: &[u8], total| {
+        if let Some(track_hashers) = &mut track_hashers {
+            track_hashers.update(chunk);
+        }
+        if let Some(on_progress) = &mut on_progress {
+            bytes_written += chunk.len() as u64;
+            on_progress(bytes_written, total);
+        }
+    };
+    let checksum = if args.crc32 { Some(Checksum::Crc32) } else { args.checksum };
+    let options = binmerge_rs::MergeOptions {
+        overwrite: args.overwrite,
+        buffer_size: args.buffer_size,
+        checksum,
+        retries: args.retries,
+        on_chunk: Some(&mut on_chunk),
+    };
+    // Zero filling, checksums and pregap modes conflict with --append, so every file is there in full
+    let (_, checksums) = if args.append {
+        append_files(merged_bin, &files, options)?
+    } else {
+        merge_files_with(merged_bin, &files, options)?
+    };
+    if let (Some(checksum), Some(checksums)) = (checksum, checksums) {
+        for (file, digest) in files.iter().zip(&checksums.inputs) {
+            if let Some(path) = file.path {
+                println!("{}  {}", digest, path.display());
             }
         }
-        // Process track lines
-        if let Some(caps) = TRACK_PATTERN.captures(&line) {
-            let start_track = Instant::now();
+        println!("{}  {}", checksums.merged, merged_bin.display());
+        write_checksum_sidecar(merged_bin, checksum, &checksums.merged, args.overwrite)?;
+    }
+    Ok(track_hashers.map(TrackHashers::finalize))
+}
 
-            if let (Some(track_number_match), Some(track_type_match)) = (caps.get(1), caps.get(2)) {
-                let track_number = track_number_match.as_str().parse::<u32>().unwrap();
-                let track_type = track_type_match.as_str().to_string();
+// The merged image to append to, which has to be a single bin of whole sectors whose cue sheet references it, or
+// the appended tracks wouldn't start where the cue sheet says
+fn merged_image(merged_bin: &Path, merged_cue: &Path) -> Result<BinFile, BinmergeError> {
+    if !merged_bin.exists() || !merged_cue.exists() {
+        let missing = format!("{} and {}", merged_bin.display(), merged_cue.display());
+        return Err(format!("Nothing to append to, {} must both exist", missing).into());
+    }
+    let mut bin_files = get_bin_from_cue_with(&merged_cue.to_string_lossy(), &ParseOptions::default())?.0;
+    let bin_file = match bin_files.pop() {
+        Some(bin_file) if bin_files.is_empty() => bin_file,
+        _ => return Err(format!("{} must reference a single bin to be appended to", merged_cue.display()).into()),
+    };
+    if fs::canonicalize(&bin_file.filename)? != fs::canonicalize(merged_bin)? {
+        return Err(format!("{} doesn't reference {}", merged_cue.display(), merged_bin.display()).into());
+    }
+    if bin_file.sector_padding() > 0 {
+        return Err(format!("{} is not a whole number of sectors", merged_bin.display()).into());
+    }
+    Ok(bin_file)
+}
 
-                if let Some(file_index) = current_file_index {
-                    let current_track = Track::new(track_number, track_type);
-                    bin_files[file_index].tracks.push(current_track);
-                    current_track_index = Some(bin_files[file_index].tracks.len() - 1);
-                    current_index_index = None;
-                }
+// Appended tracks carry on from the last track of the merged image, or from first_track, whatever their numbers in
+// their own cue sheet
+fn renumber_appended_tracks(bin_files: &mut [BinFile], first_track: Option<u32>) -> Result<(), BinmergeError> {
+    let last_track = bin_files[0].tracks.iter().map(|track| track.num).max().unwrap_or(0);
+    let first_track = first_track.unwrap_or(last_track + 1);
+    if first_track <= last_track {
+        let last = format!("track {} of the merged image", last_track);
+        return Err(format!("--first-track {} doesn't come after {}", first_track, last).into());
+    }
+    for (num, track) in (first_track..).zip(bin_files[1..].iter_mut().flat_map(|bin_file| &mut bin_file.tracks)) {
+        track.num = num;
+    }
+    Ok(())
+}
 
-                let duration_tracks = start_track.elapsed();
-                println!("Time elapsed in Track::new() is: {:?}", duration_tracks);
+fn split(args: &SplitArgs) -> Result<bool, BinmergeError> {
+    let only_tracks = args.only_tracks.as_deref().map(parse_track_list).transpose()?;
+    let trailing = match args.trailing {
+        TrailingData::Include => Trailing::Include,
+        TrailingData::Drop => Trailing::Drop,
+        TrailingData::Error => Trailing::Error,
+    };
+    split_bin_with(&args.input, &args.outdir, &args.track_template, trailing, only_tracks.as_deref())
+}
 
-                continue;
-            }
-        }
-        // Process index lines
-        if let Some(caps) = INDEX_PATTERN.captures(&line) {
-            if let (Some(index_number_match), Some(timestamp_match)) = (caps.get(1), caps.get(2)) {
-                let index_number = index_number_match.as_str().parse::<u32>().unwrap();
-                let timestamp = timestamp_match.as_str().to_string();
-                //let start_index = Instant::now();
-                let file_offset = cuestamp_to_sectors(&timestamp).unwrap(); // Convert timestamp to sectors
-                //let duration_index = start_index.elapsed();
-
-                if let Some(file_index) = current_file_index {
-                    if let Some(track_index) = current_track_index {
-                        let current_index = Index::new(index_number, timestamp, file_offset);
-                        bin_files[file_index].tracks[track_index].indexes.push(current_index);
-                        current_index_index = Some(bin_files[file_index].tracks[track_index].indexes.len() - 1);
-                    }
-                }
-                //println!("Time elapsed in Index::new() is: {:?}", duration_index);
+// Concatenates the listed bins in order, which unlike merge has no cue sheet to take offsets or track types from
+fn concat(args: &ConcatArgs) -> Result<bool, BinmergeError> {
+    let bins = read_input_list(&args.input_list)?;
+    if bins.is_empty() {
+        return Err(format!("{} doesn't list any bins", args.input_list.display()).into());
+    }
+    let merged_name = format!("{}.bin", args.name);
+    let merged_bin = output_path(&args.outdir, &merged_name)?;
+    let merged_cue = output_path(&args.outdir, &format!("{}.cue", args.name))?;
 
-                continue;
+    // Check the track type and that the cue can be written before writing the bin
+    let track = match &args.cue {
+        Some(track_type) => {
+            let track_type = track_type.to_ascii_uppercase();
+            let track_sector_size = sector_size(&track_type)?;
+            if merged_cue.exists() && !args.overwrite {
+                return Err(format!("Target merged path already exists: {}", merged_cue.display()).into());
             }
+            Some(Track::new(1, track_type, track_sector_size))
         }
-    }
-    let duration = start.elapsed();
-    println!("Time elapsed in get_bin_from_cue() is: {:?}", duration);
+        None => None,
+    };
 
-    // Check if bin file is missing
-    // if missing_bin_file {
-    //     eprintln!("Bin file is missing!");
-    //     return Ok(bin_files);
-    // }
+    create_outdir(&args.outdir)?;
+    let bytes_written =
+        write_via_partial(&merged_bin, args.overwrite, |outfile| merge_to_writer(&bins, outfile, args.buffer_size))?;
+    println!("Wrote {} ({} bytes from {} bins)", merged_bin.display(), bytes_written, bins.len());
 
-    Ok(bin_files)
+    if let Some(mut track) = track {
+        let mut bin_file = BinFile::new(merged_bin, "BINARY".to_string())?;
+        if bin_file.size.unwrap_or(0) % track.sector_size as u64 != 0 {
+            warn!("{} is not a whole number of {} byte sectors", bin_file.filename.display(), track.sector_size);
+        }
+        track.indexes.push(Index::new(1, "00:00:00".to_string(), 0));
+        bin_file.tracks.push(track);
+        write_merged_cue(&[bin_file], &merged_cue, &merged_name, args.overwrite, LineEnding::native())?;
+        println!("Wrote {}", merged_cue.display());
+    }
+    Ok(true)
 }
 
-fn get_cd_from_cue(cue_path : &str) -> io::Result<rcue::cue::Cue> {
-    println!("Cue path: {}", cue_path);
-    match Path::new(cue_path).exists() {
-        true => println!("Cue file exists!"),
-        false => {
-            eprintln!("Cue file does not exist!");
-            //return Ok(CD::parse("".to_string()).unwrap());
+// Removes the input bins and cue sheet, unless one of them is also one of the outputs
+fn remove_sources(input: &str, bin_files: &[BinFile], outputs: &[&Path]) -> io::Result<()> {
+    let mut sources: Vec<&Path> = bin_files.iter().map(|bin_file| bin_file.filename.as_path()).collect();
+    if input != "-" {
+        sources.push(Path::new(input));
+    }
+
+    let outputs = outputs.iter().map(fs::canonicalize).collect::<io::Result<Vec<_>>>()?;
+    for source in &sources {
+        if outputs.contains(&fs::canonicalize(source)?) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Not removing the source files, {} is also an output", source.display()),
+            ));
         }
-        
     }
-    let cd = parse_from_file(cue_path, true).unwrap();
-    println!("CD: {:?}", cd);
-    println!("CD Title: {:?}", cd.title);
 
+    for source in sources {
+        fs::remove_file(source)?;
+        println!("Removed {}", source.display());
+    }
+    Ok(())
+}
 
-    // let cue_file = File::open(cue_path)?;
-    // // Read cue file and store it in a single string variable
-    // let mut cue_contents = String::new();
-    // let mut reader = io::BufReader::new(cue_file);
-    // reader.read_to_string(&mut cue_contents)?;
+// Merges under --name, or the name of the input cue sheet made safe the way --rename names are
+fn merge_named(args: &MergeArgs, verbose: bool) -> Result<bool, BinmergeError> {
+    let name = match &args.name {
+        Some(name) => name.clone(),
+        None if args.input == "-" => return Err("--name is needed to merge a cue sheet read from stdin".into()),
+        None => sanitize_filename(&Path::new(&args.input).file_stem().unwrap_or_default().to_string_lossy()),
+    };
+    merge(&args.input, args.basedir.as_deref(), &args.outdir, &name, &args.options, verbose)?;
+    Ok(true)
+}
 
-    // let cd = CD::parse(cue_contents.to_string()).unwrap();
+// Merges every cue sheet under the input directory into the same relative directory under outdir, carrying on
+// past failures
+fn batch(args: &BatchArgs, verbose: bool) -> Result<bool, BinmergeError> {
+    let cues: Vec<PathBuf> = files_recursive(&args.input, args.max_depth)?
+        .into_iter()
+        .filter(|path| path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("cue")))
+        .collect();
+    // Named before any job starts so that the same cue always gets the same name, whichever job merges it
+    let cues: Vec<(PathBuf, String)> = if args.flat {
+        let names = flat_names(&cues);
+        cues.into_iter().zip(names).collect()
+    } else {
+        cues.into_iter()
+            .map(|cue| {
+                let name = cue.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+                (cue, name)
+            })
+            .collect()
+    };
 
-    // println!("Number of tracks: {}", cd.get_track_count());
-    // let mode = match cd.get_mode() {
-    //     DiscMode::CD_DA => "CD-DA",
-    //     DiscMode::CD_ROM => "CD-ROM",
-    //     DiscMode::CD_ROM_XA => "CD-ROM XA",
-    // };
-    // println!("Mode: {}", mode);
-    // println!("");
+    // Each job takes the next cue that no other job has started on
+    let next_cue = AtomicUsize::new(0);
+    let merge_cues = || {
+        let mut outcomes = Vec::new();
+        while let Some((cue, name)) = cues.get(next_cue.fetch_add(1, Ordering::Relaxed)) {
+            println!("Merging {}", cue.display());
+            let outdir = if args.flat {
+                args.outdir.clone()
+            } else {
+                let relative_dir = cue.parent().and_then(|dir| dir.strip_prefix(&args.input).ok());
+                args.outdir.join(relative_dir.unwrap_or(Path::new("")))
+            };
 
-    // for (index, track) in cd.tracks().iter().enumerate() {
-    //     println!("Track {}", index + 1);
-    //     println!("Filename: {}", track.get_filename());
-    //     println!("Start: {}", track.get_start());
-    //     println!("Length: {:?}", track.get_length());
-    //     println!("Pregap: {:?}", track.get_zero_pre());
-    //     println!("Postgap: {:?}", track.get_zero_post());
-    //     println!("");
-    // }
+            let outcome = merge(&cue.to_string_lossy(), None, &outdir, name, &args.options, verbose);
+            if let Err(e) = &outcome {
+                error!("{}: {}", cue.display(), e);
+            }
+            outcomes.push((outdir, outcome.ok()));
+        }
+        outcomes
+    };
+    let outcomes: Vec<_> = thread::scope(|scope| {
+        let jobs: Vec<_> = (0..args.jobs).map(|_| scope.spawn(merge_cues)).collect();
+        jobs.into_iter().flat_map(|job| job.join().unwrap()).collect()
+    });
 
-    Ok(cd)
-}
+    let (mut merged, mut skipped, mut failed) = (0, 0, 0);
+    let mut merged_cues: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+    for (outdir, outcome) in outcomes {
+        match outcome {
+            Some(MergeOutcome::Merged(merged_cue)) => {
+                merged += 1;
+                merged_cues.entry(outdir).or_default().push(merged_cue);
+            }
+            Some(MergeOutcome::Skipped) => skipped += 1,
+            None => failed += 1,
+        }
+    }
 
-fn merge_files(merged_filename: &str, files: Vec<&str>) -> io::Result<bool> {
-    if Path::new(merged_filename).exists() {
-        eprintln!("Target merged bin path already exists: {}", merged_filename);
-        return Ok(false);
+    // Only directories that ended up with several discs get a playlist
+    if let Some(m3u_name) = args.m3u.as_ref().filter(|_| !args.options.dry_run) {
+        for (outdir, cues) in merged_cues.iter().filter(|(_, cues)| cues.len() > 1) {
+            let m3u = outdir.join(format!("{}.m3u", m3u_name));
+            match write_m3u(&m3u, cues, args.options.overwrite) {
+                Ok(()) => println!("Wrote {}", m3u.display()),
+                Err(e) => {
+                    error!("{}: {}", m3u.display(), e);
+                    failed += 1;
+                }
+            }
+        }
     }
 
-    let mut outfile = OpenOptions::new().write(true).create_new(true).open(merged_filename)?;
+    println!("{} merged, {} skipped, {} failed", merged, skipped, failed);
+    Ok(failed == 0)
+}
 
-    let chunksize = 1024 * 1024;
-    for file in files {
-        let mut infile = File::open(file)?;
-        let mut buffer = vec![0; chunksize];
-        while let Ok(bytes_read) = infile.read(&mut buffer) {
-            if bytes_read == 0 {
+// Names for the merged files of cues that all go in the same directory, the second cue of a name and on getting it
+// numbered from 2. Names are compared ignoring case, as they are on the FAT and NTFS drives everything is put on
+fn flat_names(cues: &[PathBuf]) -> Vec<String> {
+    let mut taken = HashSet::new();
+    let mut names = Vec::new();
+    for cue in cues {
+        let stem = cue.file_stem().unwrap_or_default().to_string_lossy();
+        let mut name = stem.to_string();
+        for n in 2.. {
+            if taken.insert(name.to_lowercase()) {
                 break;
             }
-            outfile.write_all(&buffer[..bytes_read])?;
+            name = format!("{} ({})", stem, n);
         }
+        if name != stem {
+            warn!("{} is merged as {}, another cue sheet is already named {}", cue.display(), name, stem);
+        }
+        names.push(name);
     }
-    Ok(true)
+    names
 }
 
-fn read_directory(file_list: &mut Vec<String>, dir: &Path) -> io::Result<bool> {
-    match fs::read_dir(dir) {
-        Err(e) => println!("There was an error reading the directory: {}", e),
-        Ok(paths) => {
-            for path in paths {
-                match path {
-                    Err(e) => println!("There was an error with one of the entries: {}", e),
-                    Ok(p) => if p.path().is_file() {
-                        let file_name = p.file_name().into_string().unwrap();
-                        file_list.push(file_name);
-                    }
-                }
-            }
-        },
+#[derive(Parser)]
+#[command(name = "binmerge", version, about = "Merge multi-bin cue sheets into a single bin, or split them back apart")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+    /// Print the parsed cue sheet and timing information
+    #[arg(short, long, global = true)]
+    verbose: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Merge every bin referenced by a cue sheet into a single bin
+    ///
+    /// The merged bin is written with the process id and .part appended to its name and renamed once it's complete. A
+    /// merge stopped with Ctrl-C removes that .part file before exiting, while one that's killed outright or loses
+    /// power leaves it behind rather than a merged bin. Running the merge again writes a new one and leaves the old
+    /// one alone, for you to delete.
+    Merge(MergeArgs),
+    /// Split a single-bin cue sheet into one bin per track
+    Split(SplitArgs),
+    /// Merge every cue sheet found under a directory, mirroring its layout in the output directory
+    Batch(BatchArgs),
+    /// Check that the bins of a cue sheet hold exactly the tracks it describes
+    Verify(VerifyArgs),
+    /// List the tracks of a cue sheet with where they start and how long they are on the whole disc
+    List(ListArgs),
+    /// Rewrite a cue sheet with consistent quoting, indentation and track order, without touching its bins
+    NormalizeCue(NormalizeCueArgs),
+    /// Concatenate the bins listed in a file, one path per line, for loose bins without a cue sheet
+    Concat(ConcatArgs),
+    /// Merge the bins of a cue sheet and split them back out, laying every track bin and index out afresh
+    Reindex(ReindexArgs),
+}
+
+#[derive(Args)]
+struct MergeArgs {
+    /// Cue sheet referencing the bins to merge, or - to read it from stdin
+    #[arg(short, long)]
+    input: String,
+    /// Directory bin paths in the cue sheet are relative to, by default the cue sheet's directory or the current
+    /// directory when reading from stdin
+    #[arg(long)]
+    basedir: Option<PathBuf>,
+    /// Directory the merged files are written to, created if it doesn't exist
+    #[arg(short, long)]
+    outdir: PathBuf,
+    /// Base name of the merged bin and cue, by default the name of the input cue sheet
+    #[arg(short, long)]
+    name: Option<String>,
+    #[command(flatten)]
+    options: MergeOptions,
+}
+
+#[derive(Args)]
+struct BatchArgs {
+    /// Directory searched recursively for cue sheets
+    #[arg(short, long)]
+    input: PathBuf,
+    /// Only search this many levels of subdirectories below the input directory, 0 being the input directory alone
+    #[arg(long, value_name = "DEPTH")]
+    max_depth: Option<usize>,
+    /// Directory the merged files are written to, each cue's bin and cue named after it
+    #[arg(short, long)]
+    outdir: PathBuf,
+    /// Write every merged bin and cue straight to the output directory instead of mirroring the input directory's
+    /// layout. Cue sheets with the same name are told apart by a (2), (3)... after the name, in path order
+    #[arg(long, conflicts_with_all = ["m3u", "rename"])]
+    flat: bool,
+    /// Number of cue sheets merged at the same time
+    #[arg(short, long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    jobs: u32,
+    /// Also write a `<M3U>.m3u` playlist of the merged discs, in `(Disc N)` order, in every output directory that
+    /// holds more than one
+    #[arg(long, conflicts_with = "no_cue")]
+    m3u: Option<String>,
+    #[command(flatten)]
+    options: MergeOptions,
+}
+
+fn parse_remap(remap: &str) -> Result<(String, String), String> {
+    match remap.split_once('=') {
+        Some((from, to)) if !from.is_empty() => Ok((from.to_string(), to.to_string())),
+        _ => Err(format!("expected FROM=TO, got {}", remap)),
     }
-    Ok(true)
 }
 
-fn files(dir: &Path) -> Result<Vec<PathBuf>, io::Error> {
-    Ok(fs::read_dir(dir)?
-        .into_iter()
-        .filter(|r| r.is_ok()) // Get rid of Err variants for Result<DirEntry>
-        .map(|r| r.unwrap().path()) // This is safe, since we only have the Ok variants
-        .filter(|r| r.is_file()) // Filter out non-files
-        .collect())
+// Sizes are in binary units, as disc images usually are, so 700M is the 700 MiB of an 80 minute CD
+fn parse_size(size: &str) -> Result<u64, String> {
+    let (digits, shift) = match size.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&size[..i], 10),
+        Some((i, 'm' | 'M')) => (&size[..i], 20),
+        Some((i, 'g' | 'G')) => (&size[..i], 30),
+        Some((i, 't' | 'T')) => (&size[..i], 40),
+        _ => (size, 0),
+    };
+    digits.parse::<u64>()
+        .ok()
+        .and_then(|value| value.checked_mul(1 << shift))
+        .ok_or(format!("expected a number of bytes with an optional K, M, G or T suffix, got {}", size))
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum LineEndings {
+    /// Same as the input cue sheet, or the platform's for stdin
+    Auto,
+    Crlf,
+    Lf,
+}
+
+// What to do with a partial sector at the end of a bin, see Trailing
+#[derive(Clone, Copy, ValueEnum)]
+enum TrailingData {
+    /// Keep it at the end of the last track's bin
+    Include,
+    /// Leave it out, so the last track's bin is whole sectors
+    Drop,
+    /// Fail before writing anything
+    Error,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ListFormat {
+    /// Tab separated columns
+    Text,
+    /// Comma separated columns, quoted where needed, for spreadsheets
+    Csv,
+}
+
+// The 2 second pregap before track 1 of every disc is never in the cue sheet, so it's left alone by every mode
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum PregapMode {
+    /// Keep pregaps as the cue sheet has them, stored in the bins as INDEX 00 or left out as PREGAP
+    Preserve,
+    /// Store every PREGAP in the merged bin as zeros, starting the track at an INDEX 00
+    Embed,
+    /// Leave every INDEX 00 pregap out of the merged bin, turning it into a PREGAP
+    Strip,
+}
+
+#[derive(Args)]
+struct MergeOptions {
+    /// Replace the merged bin and cue if they already exist
+    #[arg(long)]
+    overwrite: bool,
+    /// Write a renamed copy even when the cue sheet already references a single bin
+    #[arg(long)]
+    force: bool,
+    /// Size in bytes of the buffers used to copy the bins
+    #[arg(long, default_value_t = DEFAULT_BUFFER_SIZE)]
+    buffer_size: usize,
+    /// Retry a read or write of a bin that timed out or would block up to N times, waiting 100 ms before the first
+    /// retry and twice as long before each next one, for bins on a slow network share
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    retries: u32,
+    /// Name the merged bin and cue after a template instead, where {name} is the name and {title} the disc TITLE
+    /// of the cue sheet, with characters FAT and NTFS don't allow replaced
+    #[arg(long, value_name = "TEMPLATE")]
+    rename: Option<String>,
+    /// Extension of the merged bin, e.g. img or iso
+    #[arg(long, default_value = "bin")]
+    ext: String,
+    /// Line endings of the merged cue sheet
+    #[arg(long, value_enum, default_value_t = LineEndings::Auto)]
+    line_endings: LineEndings,
+    /// How to merge track pregaps, which have to be at the start of their bin for embed and strip
+    #[arg(long, value_enum, default_value_t = PregapMode::Preserve, conflicts_with = "append")]
+    pregap_mode: PregapMode,
+    /// Refuse to write a merged bin larger than SIZE bytes, or kibibytes, mebibytes, gibibytes or tebibytes with a
+    /// K, M, G or T suffix, e.g. 700M, in case the cue sheet references the wrong bins
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    max_size: Option<u64>,
+    /// Pad every bin that isn't a whole number of sectors with zero bytes up to the next sector
+    #[arg(long)]
+    pad_to_sector: bool,
+    /// Print the percentage of the merged bin written so far
+    #[arg(long)]
+    progress: bool,
+    /// Delete the input bins and cue sheet once the merged bin has been checked to add up to them
+    #[arg(long)]
+    remove_source: bool,
+    /// Write zeros in place of missing bins instead of failing, sized from their index stamps. For recovering
+    /// incomplete sets only, the merged image won't match the original disc
+    #[arg(long, conflicts_with = "remove_source")]
+    zero_fill_missing: bool,
+    /// Start a track that only has an INDEX 00 at that index, and read stamps with 60 seconds or more or 75 frames or
+    /// more and empty bins, instead of failing
+    #[arg(long)]
+    lenient: bool,
+    /// Fail instead of warning when the tracks aren't numbered 1 to N in cue sheet order
+    #[arg(long)]
+    strict_numbering: bool,
+    /// Fail instead of warning when a bin ends more than a sector before its last index stamp
+    #[arg(long)]
+    strict_sizes: bool,
+    /// Replace the start of the bin paths in the cue sheet before looking for them, e.g. D:\games=/home/me/games for
+    /// a cue sheet made on another machine. Can be given more than once, the first match is used
+    #[arg(long, value_name = "FROM=TO", value_parser = parse_remap)]
+    remap: Vec<(String, String)>,
+    /// Append the bins to the end of an existing merged bin of the same name and add their tracks to its cue sheet,
+    /// numbered on from its last track or from --first-track. The merged bin must be whole sectors and its cue sheet
+    /// must reference only it
+    #[arg(long, conflicts_with_all = ["checksum", "crc32", "zero_fill_missing", "overwrite"])]
+    append: bool,
+    /// Number the appended tracks from this track instead of from the one after the last track of the merged image,
+    /// which it has to come after
+    #[arg(long, value_name = "TRACK", requires = "append", value_parser = clap::value_parser!(u32).range(1..=99))]
+    first_track: Option<u32>,
+    /// Only write the merged bin, without a cue sheet
+    #[arg(long, conflicts_with = "remove_source")]
+    no_cue: bool,
+    /// Only write the merged cue sheet, for a merged bin that already exists and adds up to the input bins
+    #[arg(long, conflicts_with_all = ["no_cue", "append", "remove_source", "checksum", "crc32"])]
+    cue_only: bool,
+    /// Print the input bins, merged size and merged cue sheet without writing anything
+    #[arg(long)]
+    dry_run: bool,
+    /// Print a digest of every input bin and the merged bin (md5, sha1 or crc32), and write it next to the merged bin
+    #[arg(long)]
+    checksum: Option<Checksum>,
+    /// Same as --checksum crc32, printing the CRC32 of every bin in uppercase hex as redump lists them
+    #[arg(long, conflicts_with = "checksum")]
+    crc32: bool,
+    /// Add a REM CRC-32 line to every track of the merged cue sheet with the CRC32 of the bytes splitting the track
+    /// back out would give, to check single tracks against a DAT. Cue sheet readers skip REM lines
+    #[arg(long, conflicts_with_all = ["append", "cue_only", "no_cue"])]
+    annotate_crc: bool,
+}
+
+#[derive(Args)]
+struct VerifyArgs {
+    /// Cue sheet referencing the bins to check
+    #[arg(short, long)]
+    input: String,
+    /// Print a tab separated table of every track's sectors and bytes across the whole disc instead
+    #[arg(long)]
+    sectors: bool,
+    /// Print the parsed bins, tracks and indexes along with the result of every check as JSON instead
+    #[arg(long, conflicts_with = "sectors")]
+    json: bool,
+    /// Print a tab separated table of the gap between every track's INDEX 01 and the next one instead, flagging gaps
+    /// that are negative, longer than a disc or shorter than 4 seconds
+    #[arg(long, conflicts_with_all = ["sectors", "json"])]
+    check_gaps: bool,
+}
+
+#[derive(Args)]
+struct ListArgs {
+    /// Cue sheet referencing the bins to list
+    #[arg(short, long)]
+    input: String,
+    /// Format of the track list
+    #[arg(long, value_enum, default_value_t = ListFormat::Text)]
+    format: ListFormat,
+}
+
+#[derive(Args)]
+struct NormalizeCueArgs {
+    /// Cue sheet to rewrite
+    #[arg(short, long)]
+    input: String,
+    /// Where to write the rewritten cue sheet instead of replacing the input
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// List bins in another directory relative to the rewritten cue sheet rather than by absolute path
+    #[arg(long)]
+    relative_paths: bool,
+    /// Replace Windows backslashes in bin paths with the platform's separator instead of keeping them as written
+    #[arg(long)]
+    normalize_paths: bool,
+}
+
+#[derive(Args)]
+struct ConcatArgs {
+    /// File listing the bins to concatenate in order, one path per line relative to the file's directory
+    #[arg(long)]
+    input_list: PathBuf,
+    /// Directory the concatenated bin is written to, created if it doesn't exist
+    #[arg(short, long)]
+    outdir: PathBuf,
+    /// Name of the concatenated bin, without extension
+    #[arg(short, long)]
+    name: String,
+    /// Also write a cue sheet with a single track of this type starting at the beginning of the bin, e.g. MODE2/2352
+    #[arg(long, value_name = "TRACK_TYPE")]
+    cue: Option<String>,
+    /// Replace the bin and cue if they already exist
+    #[arg(long)]
+    overwrite: bool,
+    /// Size in bytes of the buffers used to copy the bins
+    #[arg(long, default_value_t = DEFAULT_BUFFER_SIZE)]
+    buffer_size: usize,
+}
+
+#[derive(Args)]
+struct ReindexArgs {
+    /// Cue sheet referencing the bins to reindex
+    #[arg(short, long)]
+    input: String,
+    /// Directory the reindexed files are written to, created if it doesn't exist
+    #[arg(short, long)]
+    outdir: PathBuf,
+    /// Names of the track bins, where {base} is the name of the cue sheet and {num} the track number, padded with
+    /// zeros to N digits by {num:0N}
+    #[arg(long, default_value = DEFAULT_TRACK_TEMPLATE)]
+    track_template: String,
+}
+
+#[derive(Args)]
+struct SplitArgs {
+    /// Cue sheet referencing the bin to split
+    #[arg(short, long)]
+    input: String,
+    /// Directory the split files are written to, created if it doesn't exist
+    #[arg(short, long)]
+    outdir: PathBuf,
+    /// Names of the track bins, where {base} is the name of the cue sheet and {num} the track number, padded with
+    /// zeros to N digits by {num:0N}
+    #[arg(long, default_value = DEFAULT_TRACK_TEMPLATE)]
+    track_template: String,
+    /// What to do with a partial sector at the end of a bin
+    #[arg(long, value_enum, default_value_t = TrailingData::Include)]
+    trailing: TrailingData,
+    /// Write the AUDIO tracks as WAV files named after the track template, instead of splitting every track into a
+    /// bin. Data tracks are skipped
+    #[arg(long, conflicts_with_all = ["trailing", "only_tracks"])]
+    wav_export: bool,
+    /// Only split out these tracks, as a list of numbers and ranges such as 2,5-7, along with a cue sheet that
+    /// references just them
+    #[arg(long, value_name = "TRACKS")]
+    only_tracks: Option<String>,
 }
 
 fn main() {
-    // ---- Read Cue File tests ----
-    let path = Path::new("D:\\Downloads\\binmergetests\\Mortal Kombat 3 (USA)");
-    // Find cue file by its extension
-    let start = Instant::now();
-    let cue_path = path.join(path.file_name().unwrap()).with_extension("cue");
-    let bin_files = get_bin_from_cue(cue_path.to_str().unwrap());
-    //let _ = get_cd_from_cue(cue_path.to_str().unwrap());
-    let duration = start.elapsed();
-
-    // Print bin files
-    match bin_files {
-        Ok(bin_files) => print_bin_files(&bin_files),
-        Err(e) => println!("Error: {}", e),
-    }
-
-    println!("Time elapsed in files() is: {:?}", duration);
-
-    // ---- Read Cue File tests ----
-
-
-    // ---- Merge Files tests ----
-    // Example usage
-    //let result = merge_files("output_file.bin", vec!["file1.bin", "file2.bin"]);
-    // ---- Merge Files tests ----
-    
-    
-    // ---- Directory Reading tests ----
-    // let start = Instant::now();
-    // let path = Path::new("D:\\Downloads\\GB");
-    // let result = files(path);
-    // println!("{} files added successfully!", result.unwrap().len());
-    // let duration = start.elapsed();
-    // println!("Time elapsed in files() is: {:?}", duration);
-
-
-    // let start = Instant::now();
-    // let mut file_list: Vec<String> = Vec::new();
-
-    // let result = read_directory(&mut file_list, path);
-
-    // match result {
-    //     Ok(_) => {
-    //         println!("{} files added successfully!", file_list.len());
-    //         let duration = start.elapsed();
-    //         println!("Time elapsed in read_directory() is: {:?}", duration);
-    //     }
-    //     Err(e) => println!("Error listing files: {}", e),
-    // }
-    // ---- Directory Reading tests ----
-    
+    let cli = Cli::parse();
+    init_logger(cli.verbose);
+    if let Err(e) = remove_partial_bins_on_interrupt() {
+        warn!("Ctrl-C won't remove the partial merged bin: {}", e);
+    }
+
+    let result = match &cli.command {
+        Command::Merge(args) => merge_named(args, cli.verbose),
+        Command::Split(args) if args.wav_export => export_wav(&args.input, &args.outdir, &args.track_template),
+        Command::Split(args) => split(args),
+        Command::Batch(args) => batch(args, cli.verbose),
+        Command::Concat(args) => concat(args),
+        Command::Reindex(args) => reindex(&args.input, &args.outdir, &args.track_template),
+        Command::List(args) => print_track_list(&args.input, args.format == ListFormat::Csv),
+        Command::Verify(args) if args.sectors => print_sector_report(&args.input),
+        Command::Verify(args) if args.json => verify_bin_json(&args.input),
+        Command::Verify(args) if args.check_gaps => print_gap_report(&args.input),
+        Command::Verify(args) => verify_bin(&args.input),
+        Command::NormalizeCue(args) => {
+            let out_cue = args.output.as_deref();
+            normalize_cue(&args.input, out_cue, args.relative_paths, args.normalize_paths).map(|out_cue| {
+                println!("Wrote {}", out_cue.display());
+                true
+            })
+        }
+    };
+
+    match result {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            error!("{}", e);
+            process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rust_log_sets_levels_for_every_module_or_some() {
+        let spec = "info,binmerge_rs::merge=debug, binmerge_rs::cue=off";
+        let (filter, invalid) = LogFilter::parse(spec, LevelFilter::Warn);
+        assert!(invalid.is_empty());
+        assert_eq!(filter.level("binmerge"), LevelFilter::Info);
+        assert_eq!(filter.level("binmerge_rs::merge"), LevelFilter::Debug);
+        assert_eq!(filter.level("binmerge_rs::merged"), LevelFilter::Info);
+        assert_eq!(filter.level("binmerge_rs::cue"), LevelFilter::Off);
+        assert_eq!(filter.max_level(), LevelFilter::Debug);
+
+        // The most specific module wins, whatever order they're listed in
+        let (filter, _) = LogFilter::parse("binmerge_rs::merge=trace,binmerge_rs=error", LevelFilter::Warn);
+        assert_eq!(filter.level("binmerge_rs::merge::inner"), LevelFilter::Trace);
+        assert_eq!(filter.level("binmerge_rs::split"), LevelFilter::Error);
+        assert_eq!(filter.level("rcue"), LevelFilter::Warn);
+
+        let (filter, _) = LogFilter::parse("binmerge_rs", LevelFilter::Warn);
+        assert_eq!(filter.level("binmerge_rs::split"), LevelFilter::Trace);
+        assert_eq!(filter.level("rcue"), LevelFilter::Warn);
+
+        let (filter, _) = LogFilter::parse("binmerge_rs=debug,binmerge_rs=error", LevelFilter::Warn);
+        assert_eq!(filter.level("binmerge_rs"), LevelFilter::Error);
+    }
+
+    #[test]
+    fn rust_log_directives_that_cant_be_read_are_returned() {
+        let (filter, invalid) = LogFilter::parse("debug,binmerge_rs=loud,=info,error/merg.*", LevelFilter::Warn);
+        assert_eq!(invalid, ["binmerge_rs=loud", "=info", "error/merg.*"]);
+        assert_eq!(filter.level("binmerge_rs"), LevelFilter::Debug);
+        assert!(filter.modules.is_empty());
+    }
+
+    #[test]
+    fn sizes_are_in_binary_units() {
+        assert_eq!(parse_size("1024"), Ok(1024));
+        assert_eq!(parse_size("1k"), Ok(1024));
+        assert_eq!(parse_size("700M"), Ok(700 << 20));
+        assert_eq!(parse_size("4G"), Ok(4 << 30));
+        assert_eq!(parse_size("2T"), Ok(2 << 40));
+    }
+
+    #[test]
+    fn sizes_that_arent_numbers_or_overflow_are_refused() {
+        for size in ["", "M", "1.5G", "-1", "7Q", "17179869184G"] {
+            assert!(parse_size(size).is_err(), "{}", size);
+        }
+    }
 }
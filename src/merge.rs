@@ -0,0 +1,793 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use lazy_static::lazy_static;
+use log::warn;
+
+use regex::Regex;
+
+use crate::checksum::{Checksum, Checksums, Hasher};
+use crate::cue::{push_track, BinFile, CueParseError, LineEnding};
+use crate::error::BinmergeError;
+use crate::split::track_extent;
+
+lazy_static! {
+    static ref DISC_PATTERN: Regex = Regex::new(r"(?i)\(Disc (\d+)\)").unwrap();
+    // Partial bins this process created and hasn't renamed or removed yet
+    static ref PARTIAL_BINS: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+}
+
+// Refuses to replace an existing output unless overwrite is set, in which case it is truncated
+pub(crate) fn create_output(path: &Path, overwrite: bool, kind: &str) -> io::Result<File> {
+    if overwrite {
+        OpenOptions::new().write(true).truncate(true).create(true).open(path)
+    } else {
+        check_output(path, kind)?;
+        OpenOptions::new().write(true).create_new(true).open(path)
+    }
+}
+
+fn check_output(path: &Path, kind: &str) -> io::Result<()> {
+    if path.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("Target merged {} path already exists: {}", kind, path.display()),
+        ));
+    }
+    Ok(())
+}
+
+/// Where this process writes the merged bin at `merged_filename` until it's complete, the same path with the process
+/// id and `.part` appended, e.g. `game.bin.1234.part`.
+pub fn partial_path(merged_filename: &Path) -> PathBuf {
+    let mut partial = merged_filename.as_os_str().to_owned();
+    partial.push(format!(".{}.part", process::id()));
+    PathBuf::from(partial)
+}
+
+/// Removes the partial bins of the merges this process is still writing, and no other `.part` file, for a Ctrl-C
+/// handler to call right before the process exits. The merges themselves are left running, so anything they write
+/// after this is lost.
+pub fn remove_partial_bins() {
+    for partial in PARTIAL_BINS.lock().unwrap().drain(..) {
+        if let Err(e) = fs::remove_file(&partial) {
+            // Not found when the merge got to rename it in the meantime
+            if e.kind() != io::ErrorKind::NotFound {
+                warn!("Could not remove the partial bin {}: {}", partial.display(), e);
+            }
+        }
+    }
+}
+
+/// The file type shared by every bin, which the merged bin keeps.
+///
+/// Fails when the bins mix file types, or use a type with headers (`WAVE`, `AIFF`, `MP3`) that can't be merged
+/// by concatenating the files.
+pub fn merged_format(bin_files: &[BinFile]) -> io::Result<&str> {
+    let format = bin_files.first().map_or("BINARY", |bin_file| bin_file.format.as_str());
+
+    if let Some(other) = bin_files.iter().find(|bin_file| bin_file.format != format) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Cannot merge {} and {} files, mixed file types aren't supported", format, other.format),
+        ));
+    }
+    check_raw_format(format, "merge")?;
+
+    Ok(format)
+}
+
+// Only BINARY and MOTOROLA files are raw sectors, which can be cut and concatenated anywhere. The others have headers
+pub(crate) fn check_raw_format(format: &str, action: &str) -> io::Result<()> {
+    if !matches!(format, "BINARY" | "MOTOROLA") {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Cannot {} {} files, only BINARY and MOTOROLA files hold raw sectors", action, format),
+        ));
+    }
+    Ok(())
+}
+
+/// Default size of the read and write buffers used by [`merge_files`].
+///
+/// Merging a 700 MB image, 4 KiB buffers take about twice as long as 64 KiB ones and throughput levels off past
+/// that, so 1 MiB keeps some headroom for slower disks while larger buffers only cost memory.
+pub const DEFAULT_BUFFER_SIZE: usize = 1024 * 1024;
+
+// Runs op until it succeeds, retrying interrupted calls right away, and calls that timed out or would block up to
+// retries times after a backoff doubling from 100 ms. Those only fail on network filesystems when the share is slow
+// to answer, so trying again later usually goes through.
+fn retry<T>(retries: u32, mut op: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+    let mut attempt = 0;
+    loop {
+        match op() {
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) if matches!(e.kind(), io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock) && attempt < retries => {
+                let backoff = Duration::from_millis(100 << attempt.min(6));
+                warn!("{}, retrying in {} ms", e, backoff.as_millis());
+                thread::sleep(backoff);
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+// Copies infile to outfile through buffer, retrying failed reads and writes as retry does. Every chunk copied is also
+// handed to on_chunk.
+fn copy_chunks<R: Read, W: Write>(
+    infile: &mut R,
+    outfile: &mut W,
+    buffer: &mut [u8],
+    retries: u32,
+    on_chunk: &mut dyn FnMut(&[u8]),
+) -> io::Result<u64> {
+    let mut bytes_written = 0;
+    loop {
+        let bytes_read = match retry(retries, || infile.read(buffer))? {
+            0 => break,
+            bytes_read => bytes_read,
+        };
+        // write_all can't tell how much it wrote before failing, so a retry has to pick up from a single write
+        let mut chunk = &buffer[..bytes_read];
+        while !chunk.is_empty() {
+            match retry(retries, || outfile.write(chunk))? {
+                0 => return Err(io::Error::new(io::ErrorKind::WriteZero, "failed to write the whole chunk")),
+                written => chunk = &chunk[written..],
+            }
+        }
+        on_chunk(&buffer[..bytes_read]);
+        bytes_written += bytes_read as u64;
+    }
+    Ok(bytes_written)
+}
+
+/// A file to merge, along with the bytes to leave out at its start and the zero bytes to write after it.
+#[derive(Clone, Copy, Debug)]
+pub struct MergeInput<'a> {
+    /// The file, or `None` for a missing file that is written as its padding alone, to recover what's left of an
+    /// incomplete set
+    pub path: Option<&'a Path>,
+    /// Bytes at the start of the file left out of the merged bin
+    pub skip: u64,
+    /// Zero bytes written after the file
+    pub padding: u64,
+}
+
+impl<'a> MergeInput<'a> {
+    /// All of `path`, with no padding.
+    pub fn new(path: &'a Path) -> MergeInput<'a> {
+        MergeInput { path: Some(path), skip: 0, padding: 0 }
+    }
+}
+
+// Called with a chunk written and the size the merged bin will be
+type ChunkCallback<'a> = &'a mut dyn FnMut(&[u8], u64);
+
+/// How [`merge_files_with`] and [`append_files`] copy the bins. The default is what [`merge_files`] does with
+/// [`DEFAULT_BUFFER_SIZE`] buffers.
+pub struct MergeOptions<'a> {
+    /// Replace an existing merged bin
+    pub overwrite: bool,
+    /// Size in bytes of the read and write buffers
+    pub buffer_size: usize,
+    /// Digest to compute of every input bin and of the merged bin, from the chunks as they are copied so the bins
+    /// are only read once. The digest of an input bin is that of the bytes copied from it, its padding left out, and
+    /// that of a missing file is the digest of no data.
+    pub checksum: Option<Checksum>,
+    /// Times to retry a read or write that timed out (`TimedOut`) or would block (`WouldBlock`), waiting 100 ms
+    /// before the first retry and twice as long before each next one. Reads and writes that were interrupted
+    /// (`Interrupted`) are always retried, right away.
+    pub retries: u32,
+    /// Called with every chunk written to the merged bin, in order, along with the size the merged bin will be. See
+    /// [`TrackHashers`] for digests of its tracks.
+    pub on_chunk: Option<ChunkCallback<'a>>,
+}
+
+impl Default for MergeOptions<'_> {
+    fn default() -> Self {
+        MergeOptions { overwrite: false, buffer_size: DEFAULT_BUFFER_SIZE, checksum: None, retries: 0, on_chunk: None }
+    }
+}
+
+// Copies every file, from skip bytes in, to outfile followed by its padding of zero bytes, a missing file being its
+// padding alone
+fn copy_files<W: Write>(
+    files: &[MergeInput],
+    outfile: &mut W,
+    buffer_size: usize,
+    retries: u32,
+    on_chunk: &mut dyn FnMut(usize, &[u8], bool),
+) -> io::Result<u64> {
+    let mut buffer = vec![0; buffer_size];
+    let mut bytes_written = 0;
+    for (file_index, file) in files.iter().enumerate() {
+        if let Some(path) = file.path {
+            let mut infile = File::open(path)?;
+            infile.seek(SeekFrom::Start(file.skip))?;
+            let mut infile = BufReader::with_capacity(buffer_size, infile);
+            bytes_written += copy_chunks(&mut infile, outfile, &mut buffer, retries, &mut |chunk| {
+                on_chunk(file_index, chunk, false)
+            })?;
+        }
+        bytes_written += copy_chunks(
+            &mut io::repeat(0).take(file.padding),
+            outfile,
+            &mut buffer,
+            retries,
+            &mut |chunk| on_chunk(file_index, chunk, true),
+        )?;
+    }
+    retry(retries, || outfile.flush())?;
+    Ok(bytes_written)
+}
+
+// Copies the files to outfile as copy_files does, with the digests and the callback of the options, and checks the
+// bytes written add up to expected_size, the size of the files and their padding
+fn copy_with_options<W: Write>(
+    files: &[MergeInput],
+    outfile: &mut W,
+    expected_size: u64,
+    options: &mut MergeOptions,
+) -> io::Result<(u64, Option<Checksums>)> {
+    let mut hashers = options.checksum.map(|checksum| {
+        let inputs: Vec<Hasher> = files.iter().map(|_| Hasher::new(checksum)).collect();
+        (inputs, Hasher::new(checksum))
+    });
+    let on_chunk = &mut options.on_chunk;
+    let bytes_written =
+        copy_files(files, outfile, options.buffer_size, options.retries, &mut |file_index, chunk, padding| {
+            if let Some((inputs, merged)) = &mut hashers {
+                if !padding {
+                    inputs[file_index].update(chunk);
+                }
+                merged.update(chunk);
+            }
+            if let Some(on_chunk) = on_chunk {
+                on_chunk(chunk, expected_size);
+            }
+        })?;
+    // A short merge would only show up later as a corrupt image
+    if bytes_written != expected_size {
+        return Err(io::Error::other(format!(
+            "Wrote {} bytes but the input bins add up to {} bytes",
+            bytes_written, expected_size
+        )));
+    }
+
+    let checksums = hashers.map(|(inputs, merged)| Checksums {
+        inputs: inputs.into_iter().map(Hasher::finalize).collect(),
+        merged: merged.finalize(),
+    });
+    Ok((bytes_written, checksums))
+}
+
+/// Concatenates `files` into a bin at `merged_filename` with [`merge_to_writer`], returning the number of bytes
+/// written.
+///
+/// Files are copied `buffer_size` bytes at a time, see [`DEFAULT_BUFFER_SIZE`]. An existing `merged_filename`
+/// is only replaced when `overwrite` is set. The bin is written as [`write_via_partial`] writes it, so a merge that's
+/// interrupted leaves a `.part` file rather than a merged bin that looks whole.
+pub fn merge_files(
+    merged_filename: &Path,
+    files: Vec<&Path>,
+    overwrite: bool,
+    buffer_size: usize,
+) -> Result<u64, BinmergeError> {
+    check_buffer_size(buffer_size)?;
+    let inputs: Vec<MergeInput> = files.iter().copied().map(MergeInput::new).collect();
+    check_not_an_input(merged_filename, &inputs)?;
+    let files: Vec<PathBuf> = files.into_iter().map(Path::to_path_buf).collect();
+    write_via_partial(merged_filename, overwrite, |outfile| merge_to_writer(&files, outfile, buffer_size))
+}
+
+/// Like [`merge_files`] with every option of [`MergeOptions`], for files that can be cut, padded or missing,
+/// returning the digests when a checksum is asked for along with the number of bytes written.
+pub fn merge_files_with(
+    merged_filename: &Path,
+    files: &[MergeInput],
+    mut options: MergeOptions,
+) -> Result<(u64, Option<Checksums>), BinmergeError> {
+    check_buffer_size(options.buffer_size)?;
+    check_not_an_input(merged_filename, files)?;
+    // Stat the inputs once, for both the progress total and the check of the merged size
+    let expected_size = input_size(files)?;
+
+    write_via_partial(merged_filename, options.overwrite, |outfile| {
+        let mut outfile = BufWriter::with_capacity(options.buffer_size, outfile);
+        Ok(copy_with_options(files, &mut outfile, expected_size, &mut options)?)
+    })
+}
+
+/// Runs `write` on a new partial bin at [`partial_path`] and renames it to `merged_filename` once `write` returns,
+/// returning what `write` returned. An existing `merged_filename` is only replaced when `overwrite` is set.
+///
+/// No later merge touches the partial bin of a merge that's interrupted. If `write` or the rename fails, the partial
+/// bin is removed, and so is every partial bin still being written when [`remove_partial_bins`] is called.
+pub fn write_via_partial<T>(
+    merged_filename: &Path,
+    overwrite: bool,
+    write: impl FnOnce(&mut File) -> Result<T, BinmergeError>,
+) -> Result<T, BinmergeError> {
+    if !overwrite {
+        check_output(merged_filename, "bin")?;
+    }
+    // The partial path is created new so that only the file this merge made is ever removed
+    let partial = partial_path(merged_filename);
+    // Registered under the same lock, so that it can't be interrupted in between and left behind
+    let mut outfile = {
+        let mut partial_bins = PARTIAL_BINS.lock().unwrap();
+        let outfile = create_output(&partial, false, "partial bin")?;
+        partial_bins.push(partial.clone());
+        outfile
+    };
+    let result = write(&mut outfile);
+    drop(outfile);
+
+    // Checked again in case another merge wrote the same bin in the meantime, which the rename would replace
+    let result = result.and_then(|written| {
+        if !overwrite {
+            check_output(merged_filename, "bin")?;
+        }
+        fs::rename(&partial, merged_filename)?;
+        Ok(written)
+    });
+    // The error the merge failed with is the one worth reporting, not a failure to clean up after it
+    if result.is_err() {
+        if let Err(e) = fs::remove_file(&partial) {
+            warn!("Could not remove the partial bin {}: {}", partial.display(), e);
+        }
+    }
+    PARTIAL_BINS.lock().unwrap().retain(|path| *path != partial);
+    result
+}
+
+/// Concatenates `files` into `out`, copying them `buffer_size` bytes at a time, and returns the number of bytes
+/// written.
+///
+/// Fails when the bytes written don't add up to the sizes the files had when the merge started.
+pub fn merge_to_writer<W: Write>(files: &[PathBuf], out: &mut W, buffer_size: usize) -> Result<u64, BinmergeError> {
+    check_buffer_size(buffer_size)?;
+    let files: Vec<MergeInput> = files.iter().map(|file| MergeInput::new(file)).collect();
+    let expected_size = input_size(&files)?;
+    let mut options = MergeOptions { buffer_size, ..MergeOptions::default() };
+    let (bytes_written, _) = copy_with_options(&files, out, expected_size, &mut options)?;
+    Ok(bytes_written)
+}
+
+fn input_size(files: &[MergeInput]) -> io::Result<u64> {
+    let mut size = 0;
+    for file in files {
+        if let Some(path) = file.path {
+            size += fs::metadata(path)?.len().checked_sub(file.skip).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} is shorter than the {} bytes to leave out of it", path.display(), file.skip),
+                )
+            })?;
+        }
+        size += file.padding;
+    }
+    Ok(size)
+}
+
+/// Digests of every track of the bins being merged, fed the merged bin chunk by chunk as it's written, e.g. by
+/// [`MergeOptions::on_chunk`]. A track covers the bytes splitting it back out would write to its own bin, from its
+/// `INDEX 00` or `INDEX 01` to the next track or the end of its bin, so its digest is the one a DAT lists for that bin.
+pub struct TrackHashers {
+    // Start and end of every track in the merged bin, in cue sheet order
+    ranges: Vec<(u64, u64)>,
+    hashers: Vec<Hasher>,
+    position: u64,
+}
+
+impl TrackHashers {
+    /// Starts an empty `checksum` digest of every track of `bin_files`, which are merged in that order with the
+    /// sizes they have.
+    ///
+    /// Fails when a track runs past the end of its bin.
+    pub fn new(bin_files: &[BinFile], checksum: Checksum) -> io::Result<TrackHashers> {
+        let mut ranges = Vec::new();
+        let mut bin_start = 0;
+        for bin_file in bin_files {
+            for i in 0..bin_file.tracks.len() {
+                let (start, end) = track_extent(bin_file, i)?;
+                ranges.push((bin_start + start, bin_start + end));
+            }
+            bin_start += bin_file.size.unwrap_or(0);
+        }
+        let hashers = ranges.iter().map(|_| Hasher::new(checksum)).collect();
+        Ok(TrackHashers { ranges, hashers, position: 0 })
+    }
+
+    /// Adds the next `chunk` of the merged bin to the digests of the tracks it overlaps.
+    pub fn update(&mut self, chunk: &[u8]) {
+        let chunk_end = self.position + chunk.len() as u64;
+        for (&(start, end), hasher) in self.ranges.iter().zip(&mut self.hashers) {
+            let (from, to) = (start.max(self.position), end.min(chunk_end));
+            if from < to {
+                hasher.update(&chunk[(from - self.position) as usize..(to - self.position) as usize]);
+            }
+        }
+        self.position = chunk_end;
+    }
+
+    /// Finishes the digests, one per track in cue sheet order.
+    pub fn finalize(self) -> Vec<String> {
+        self.hashers.into_iter().map(Hasher::finalize).collect()
+    }
+}
+
+fn check_buffer_size(buffer_size: usize) -> io::Result<()> {
+    if buffer_size == 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Buffer size must be greater than zero"));
+    }
+    Ok(())
+}
+
+/// Appends `files` to the end of the existing bin at `merged_filename` as [`merge_files_with`] merges them, and
+/// returns the number of bytes appended along with the digests of the files and of the bytes appended.
+/// `options.overwrite` doesn't apply.
+///
+/// If any read or write fails, or the bytes appended don't add up to the files and their padding, the bin is cut
+/// back to its original size.
+pub fn append_files(
+    merged_filename: &Path,
+    files: &[MergeInput],
+    mut options: MergeOptions,
+) -> Result<(u64, Option<Checksums>), BinmergeError> {
+    check_buffer_size(options.buffer_size)?;
+    check_not_an_input(merged_filename, files)?;
+
+    let expected_size = input_size(files)?;
+    let outfile = OpenOptions::new().append(true).open(merged_filename)?;
+    let original_size = outfile.metadata()?.len();
+    let mut writer = BufWriter::with_capacity(options.buffer_size, &outfile);
+    let result = copy_with_options(files, &mut writer, expected_size, &mut options);
+
+    if result.is_err() {
+        drop(writer);
+        outfile.set_len(original_size)?;
+    }
+    Ok(result?)
+}
+
+// Truncating an input to write the merged bin over it would lose the input before it's read
+fn check_not_an_input(merged_filename: &Path, files: &[MergeInput]) -> io::Result<()> {
+    let merged = match fs::canonicalize(merged_filename) {
+        Ok(merged) => merged,
+        // A merged bin that doesn't exist yet can't be any of the inputs
+        Err(_) => return Ok(()),
+    };
+    for file in files.iter().filter_map(|file| file.path) {
+        if fs::canonicalize(file)? == merged {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Merged bin {} is also one of the bins to merge", merged_filename.display()),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Writes a cue sheet for the merged bin `merged_name`, with every index shifted to its offset in the merged bin.
+///
+/// Lines end with `line_ending`. An existing `out_cue` is only replaced when `overwrite` is set.
+pub fn write_merged_cue(
+    bin_files: &[BinFile],
+    out_cue: &Path,
+    merged_name: &str,
+    overwrite: bool,
+    line_ending: LineEnding,
+) -> Result<(), BinmergeError> {
+    let cuesheet = line_ending.apply(&render_merged_cue(bin_files, merged_name)?);
+    create_output(out_cue, overwrite, "cue")?.write_all(cuesheet.as_bytes())?;
+    Ok(())
+}
+
+/// Contents of the cue sheet written by [`write_merged_cue`].
+///
+/// Index stamps restart at zero with every `FILE`, so the stamps of each bin are shifted by the length in sectors of
+/// all the bins before it, however many tracks each of them holds. Fails with a [`CueParseError::Unmergeable`] parse
+/// error when the bins don't share a file type that can be concatenated, or hold more sectors than a cue sheet stamp
+/// can address.
+pub fn render_merged_cue(bin_files: &[BinFile], merged_name: &str) -> Result<String, BinmergeError> {
+    let mut cuesheet = String::new();
+    for bin_file in bin_files {
+        for command in &bin_file.metadata {
+            cuesheet += &format!("{}\n", command);
+        }
+    }
+    let format = merged_format(bin_files).map_err(|err| CueParseError::Unmergeable(err.to_string()))?;
+    cuesheet += &format!("FILE \"{}\" {}\n", merged_name, format);
+
+    // Index offsets are relative to their own bin, so shift them by the sectors of every bin before it
+    let too_large = || CueParseError::Unmergeable("Merged bin has too many sectors for a cue sheet".to_string());
+    let mut sector_pos: u32 = 0;
+    for bin_file in bin_files {
+        let last_offset = bin_file.tracks.iter().flat_map(|track| &track.indexes).map(|index| index.file_offset).max();
+        sector_pos.checked_add(last_offset.unwrap_or(0)).ok_or_else(too_large)?;
+        for track in &bin_file.tracks {
+            push_track(&mut cuesheet, track, |index| sector_pos + index.file_offset);
+        }
+        sector_pos = sector_pos.checked_add(bin_file.sectors()).ok_or_else(too_large)?;
+    }
+
+    Ok(cuesheet)
+}
+
+/// Writes a sidecar next to `merged_bin` listing its `checksum` digest, in the `<digest> *<filename>` format read
+/// by `md5sum -c`, `sha1sum -c` and `rhash`, or as an SFV file for a CRC32. Returns the path of the sidecar.
+///
+/// An existing sidecar is only replaced when `overwrite` is set.
+pub fn write_checksum_sidecar(merged_bin: &Path, checksum: Checksum, digest: &str, overwrite: bool) -> io::Result<PathBuf> {
+    let sidecar = merged_bin.with_extension(checksum.extension());
+    let filename = merged_bin.file_name().unwrap_or_default().to_string_lossy();
+    let line = match checksum {
+        Checksum::Crc32 => format!("{} {}\n", filename, digest),
+        _ => format!("{} *{}\n", digest, filename),
+    };
+
+    create_output(&sidecar, overwrite, "checksum")?.write_all(line.as_bytes())?;
+    Ok(sidecar)
+}
+
+/// Writes an M3U playlist at `m3u` listing `cues` in disc order, taken from the `(Disc N)` in their filenames.
+///
+/// Cues in the playlist's directory are listed by filename so the set can be moved around together. An existing
+/// `m3u` is only replaced when `overwrite` is set.
+pub fn write_m3u(m3u: &Path, cues: &[PathBuf], overwrite: bool) -> io::Result<()> {
+    let disc_number = |cue: &Path| {
+        let filename = cue.file_name().unwrap_or_default().to_string_lossy();
+        DISC_PATTERN.captures(&filename).and_then(|caps| caps[1].parse::<u32>().ok())
+    };
+    let mut cues: Vec<&PathBuf> = cues.iter().collect();
+    cues.sort_by_key(|cue| (disc_number(cue), cue.as_path()));
+
+    let m3u_dir = m3u.parent().unwrap_or(Path::new(""));
+    let mut playlist = String::new();
+    for cue in cues {
+        playlist += &format!("{}\n", cue.strip_prefix(m3u_dir).unwrap_or(cue).display());
+    }
+    create_output(m3u, overwrite, "playlist")?.write_all(playlist.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::cue::parse_cue;
+    use crate::testing::TempDir;
+
+    // Reads data in chunks of at most 4 bytes, failing with error_kind once it's read fail_after bytes
+    struct FailingReader {
+        data: Vec<u8>,
+        position: usize,
+        fail_after: usize,
+        error_kind: io::ErrorKind,
+    }
+
+    impl Read for FailingReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.position >= self.fail_after {
+                return Err(io::Error::new(self.error_kind, "injected failure"));
+            }
+            let end = self.data.len().min(self.position + 4).min(self.position + buf.len());
+            let read = end - self.position;
+            buf[..read].copy_from_slice(&self.data[self.position..end]);
+            self.position = end;
+            Ok(read)
+        }
+    }
+
+    // Fails with error_kind the first failures times it's read, then reads data
+    struct FlakyReader<'a> {
+        data: &'a [u8],
+        failures: u32,
+        error_kind: io::ErrorKind,
+    }
+
+    impl Read for FlakyReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err(io::Error::new(self.error_kind, "injected failure"));
+            }
+            self.data.read(buf)
+        }
+    }
+
+    // Takes at most 3 bytes per write
+    struct ShortWriter(Vec<u8>);
+
+    impl Write for ShortWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let written = buf.len().min(3);
+            self.0.extend_from_slice(&buf[..written]);
+            Ok(written)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn copy(reader: &mut impl Read, writer: &mut impl Write, retries: u32) -> io::Result<u64> {
+        copy_chunks(reader, writer, &mut [0; 16], retries, &mut |_| {})
+    }
+
+    #[test]
+    fn read_error_fails_the_copy() {
+        let data: Vec<u8> = (0..64).collect();
+        let mut reader = FailingReader { data, position: 0, fail_after: 32, error_kind: io::ErrorKind::Other };
+        let mut out = Vec::new();
+        let err = copy(&mut reader, &mut out, 3).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+        assert_eq!(out.len(), 32);
+    }
+
+    #[test]
+    fn short_writes_are_completed() {
+        let data: Vec<u8> = (0..64).collect();
+        let mut out = ShortWriter(Vec::new());
+        assert_eq!(copy(&mut data.as_slice(), &mut out, 0).unwrap(), 64);
+        assert_eq!(out.0, data);
+    }
+
+    #[test]
+    fn merged_bin_that_is_an_input_is_refused() {
+        let dir = TempDir::new("merge-into-input");
+        let first = dir.write("first.bin", [1; 2352]);
+        let second = dir.write("second.bin", [2; 2352]);
+        // The same file by another path is still one of the inputs
+        let same = dir.path().join(".").join("second.bin");
+        for merged in [&second, &same] {
+            let err = merge_files(merged, vec![&first, &second], true, DEFAULT_BUFFER_SIZE).unwrap_err();
+            assert!(err.to_string().contains("is also one of the bins to merge"), "{}", err);
+        }
+        let files = [MergeInput::new(&first)];
+        assert!(append_files(&first, &files, MergeOptions::default()).is_err());
+        assert_eq!(fs::read(&second).unwrap(), [2; 2352]);
+        assert_eq!(fs::read(&first).unwrap(), [1; 2352]);
+    }
+
+    #[test]
+    fn merged_cue_shifts_every_bin() {
+        let dir = TempDir::new("merge-render");
+        dir.write("game (Track 1).bin", [0; 4 * 2352]);
+        dir.write("game (Track 2).bin", [0; 5 * 2352]);
+        let cuesheet = concat!(
+            "REM GENRE Game\n",
+            "TITLE \"Game\"\n",
+            "FILE \"game (Track 1).bin\" BINARY\n",
+            "  TRACK 01 MODE1/2352\n",
+            "    INDEX 01 00:00:00\n",
+            "FILE \"game (Track 2).bin\" BINARY\n",
+            "  TRACK 02 AUDIO\n",
+            "    FLAGS DCP\n",
+            "    INDEX 00 00:00:00\n",
+            "    INDEX 01 00:00:02\n",
+        );
+        let bin_files = parse_cue(Cursor::new(cuesheet), dir.path()).unwrap();
+        assert_eq!(
+            render_merged_cue(&bin_files, "game.bin").unwrap(),
+            concat!(
+                "REM GENRE Game\n",
+                "TITLE \"Game\"\n",
+                "FILE \"game.bin\" BINARY\n",
+                "  TRACK 01 MODE1/2352\n",
+                "    INDEX 01 00:00:00\n",
+                "  TRACK 02 AUDIO\n",
+                "    FLAGS DCP\n",
+                "    INDEX 00 00:00:04\n",
+                "    INDEX 01 00:00:06\n",
+            )
+        );
+    }
+
+    #[test]
+    fn merged_cue_of_mixed_file_types_is_refused() {
+        let dir = TempDir::new("merge-render-mixed");
+        dir.write("data.bin", [0; 2352]);
+        dir.write("audio.wav", [0; 2352]);
+        let cuesheet = concat!(
+            "FILE \"data.bin\" BINARY\n",
+            "  TRACK 01 MODE1/2352\n",
+            "    INDEX 01 00:00:00\n",
+            "FILE \"audio.wav\" WAVE\n",
+            "  TRACK 02 AUDIO\n",
+            "    INDEX 01 00:00:00\n",
+        );
+        let bin_files = parse_cue(Cursor::new(cuesheet), dir.path()).unwrap();
+        assert!(matches!(
+            render_merged_cue(&bin_files, "game.bin"),
+            Err(BinmergeError::Parse(CueParseError::Unmergeable(_)))
+        ));
+    }
+
+    #[test]
+    fn reads_that_time_out_are_retried() {
+        let data: Vec<u8> = (0..64).collect();
+        let mut reader = FlakyReader { data: &data, failures: 2, error_kind: io::ErrorKind::TimedOut };
+        let mut out = Vec::new();
+        assert_eq!(copy(&mut reader, &mut out, 2).unwrap(), 64);
+        assert_eq!(out, data);
+    }
+
+    #[test]
+    fn reads_that_keep_timing_out_fail_once_out_of_retries() {
+        let data: Vec<u8> = (0..64).collect();
+        let mut reader = FlakyReader { data: &data, failures: 2, error_kind: io::ErrorKind::TimedOut };
+        assert_eq!(copy(&mut reader, &mut Vec::new(), 1).unwrap_err().kind(), io::ErrorKind::TimedOut);
+        let mut reader = FlakyReader { data: &data, failures: u32::MAX, error_kind: io::ErrorKind::WouldBlock };
+        assert_eq!(copy(&mut reader, &mut Vec::new(), 0).unwrap_err().kind(), io::ErrorKind::WouldBlock);
+    }
+
+    #[test]
+    fn interrupted_reads_are_always_retried() {
+        let data: Vec<u8> = (0..64).collect();
+        let mut reader = FlakyReader { data: &data, failures: 10, error_kind: io::ErrorKind::Interrupted };
+        assert_eq!(copy(&mut reader, &mut Vec::new(), 0).unwrap(), 64);
+    }
+
+    #[test]
+    fn existing_partial_bin_is_left_alone() {
+        let dir = TempDir::new("merge-existing-partial");
+        let input = dir.write("track.bin", [1; 2352]);
+        let merged = dir.path().join("game.bin");
+        let partial = dir.write(&partial_path(&merged).file_name().unwrap().to_string_lossy(), "not ours");
+
+        let err = merge_files(&merged, vec![&input], false, DEFAULT_BUFFER_SIZE).unwrap_err();
+        assert!(matches!(err, BinmergeError::Io(err) if err.kind() == io::ErrorKind::AlreadyExists));
+        assert_eq!(fs::read_to_string(&partial).unwrap(), "not ours");
+        assert!(!merged.exists());
+    }
+
+    // Opening a directory works but reading it doesn't, so the merge fails once its partial bin is written
+    #[cfg(unix)]
+    #[test]
+    fn failed_merge_removes_its_partial_bin() {
+        let dir = TempDir::new("merge-failed-partial");
+        let input = dir.write("track.bin", [1; 2352]);
+        let unreadable = dir.path().join("directory.bin");
+        fs::create_dir(&unreadable).unwrap();
+        let merged = dir.path().join("game.bin");
+
+        assert!(merge_files(&merged, vec![&input, &unreadable], false, DEFAULT_BUFFER_SIZE).is_err());
+        assert!(!partial_path(&merged).exists());
+        assert!(!merged.exists());
+    }
+
+    #[test]
+    fn files_are_merged_to_a_writer_in_order() {
+        let dir = TempDir::new("merge-to-writer");
+        let first = dir.write("first.bin", [1; 2352]);
+        let second = dir.write("second.bin", [2; 2352 * 2]);
+        let mut out = Vec::new();
+        // A buffer that doesn't divide the bins, so that chunks straddle where one ends
+        assert_eq!(merge_to_writer(&[first.clone(), second.clone()], &mut out, 1000).unwrap(), 2352 * 3);
+        assert_eq!(out[..2352], [1; 2352]);
+        assert_eq!(out[2352..], [2; 2352 * 2]);
+
+        let merged = dir.path().join("game.bin");
+        merge_files(&merged, vec![&first, &second], false, 1000).unwrap();
+        assert_eq!(fs::read(&merged).unwrap(), out);
+    }
+
+    #[test]
+    fn merging_to_a_writer_checks_its_arguments() {
+        let dir = TempDir::new("merge-to-writer-errors");
+        let input = dir.write("track.bin", [1; 2352]);
+        let mut out = Vec::new();
+        assert!(merge_to_writer(&[input], &mut out, 0).unwrap_err().to_string().contains("greater than zero"));
+        let missing = dir.path().join("missing.bin");
+        let err = merge_to_writer(&[missing], &mut out, DEFAULT_BUFFER_SIZE).unwrap_err();
+        assert!(matches!(err, BinmergeError::Io(err) if err.kind() == io::ErrorKind::NotFound));
+        assert!(out.is_empty());
+    }
+}
@@ -0,0 +1,148 @@
+use std::fs;
+use std::mem;
+use std::path::{self, Path, PathBuf};
+
+use crate::cue::{get_bin_from_cue, push_track, BinFile, LineEnding};
+use crate::dir::relative_path;
+use crate::error::BinmergeError;
+
+/// Rewrites the cue sheet at `cue_path` with quoted filenames and two spaces of indentation per level, keeping
+/// every command and leaving the bins alone. Returns the path written.
+///
+/// `FILE` blocks are sorted by their lowest track number and tracks are then numbered from 1 in the order written.
+/// Tracks within a bin keep their order, as it's the order of their data in the bin.
+///
+/// The cue sheet is replaced unless `out_cue` is set, in which case `out_cue` must not exist yet. Bins are listed
+/// relative to the new cue sheet when it's in the same directory as the old one. Otherwise they're listed by
+/// absolute path, or by their path relative to the new cue sheet's directory with `relative_paths`.
+///
+/// Bins listed relative to the same directory keep the path written in the old cue sheet, unless `normalize_paths`
+/// is set, in which case Windows backslashes are replaced by the platform's separator.
+pub fn normalize_cue(
+    cue_path: &str,
+    out_cue: Option<&Path>,
+    relative_paths: bool,
+    normalize_paths: bool,
+) -> Result<PathBuf, BinmergeError> {
+    if cue_path == "-" && out_cue.is_none() {
+        return Err("A cue sheet read from stdin can't be rewritten in place".into());
+    }
+    #[cfg(feature = "toc")]
+    if crate::toc::is_toc(cue_path) && out_cue.is_none() {
+        return Err("A TOC file can't be rewritten in place as a cue sheet".into());
+    }
+    let mut bin_files = get_bin_from_cue(cue_path)?;
+    sort_tracks(&mut bin_files);
+    let line_ending = LineEnding::detect(cue_path)?;
+
+    let cue_dir = Path::new(cue_path).parent().unwrap_or(Path::new(""));
+    let out_cue = out_cue.map_or(PathBuf::from(cue_path), Path::to_path_buf);
+    let out_dir = out_cue.parent().unwrap_or(Path::new(""));
+    if out_cue != Path::new(cue_path) && out_cue.exists() {
+        return Err(format!("Target normalized cue path already exists: {}", out_cue.display()).into());
+    }
+    // An empty parent is the current directory, which path::absolute doesn't accept as is
+    let absolute_dir = |dir: &Path| path::absolute(if dir.as_os_str().is_empty() { Path::new(".") } else { dir });
+    let same_dir = absolute_dir(cue_dir)? == absolute_dir(out_dir)?;
+
+    let mut cuesheet = String::new();
+    for bin_file in &bin_files {
+        for command in &bin_file.metadata {
+            cuesheet += &format!("{}\n", command);
+        }
+        let filename = if same_dir && !normalize_paths {
+            PathBuf::from(&bin_file.cue_filename)
+        } else if same_dir {
+            bin_file.filename.strip_prefix(cue_dir).unwrap_or(&bin_file.filename).to_path_buf()
+        } else if relative_paths {
+            relative_path(out_dir, &bin_file.filename)?
+        } else {
+            path::absolute(&bin_file.filename)?
+        };
+        cuesheet += &format!("FILE \"{}\" {}\n", filename.display(), bin_file.format);
+        for track in &bin_file.tracks {
+            push_track(&mut cuesheet, track, |index| index.file_offset);
+        }
+    }
+
+    fs::write(&out_cue, line_ending.apply(&cuesheet))?;
+    Ok(out_cue)
+}
+
+// The commands before the first FILE are the disc's, so they stay first whichever block ends up there
+fn sort_tracks(bin_files: &mut [BinFile]) {
+    let disc_metadata = bin_files.first_mut().map(|bin_file| mem::take(&mut bin_file.metadata)).unwrap_or_default();
+    bin_files.sort_by_key(|bin_file| bin_file.tracks.iter().map(|track| track.num).min());
+    if let Some(first) = bin_files.first_mut() {
+        first.metadata.splice(0..0, disc_metadata);
+    }
+    for (num, track) in (1..).zip(bin_files.iter_mut().flat_map(|bin_file| &mut bin_file.tracks)) {
+        track.num = num;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn filenames_are_quoted() {
+        let dir = TempDir::new("normalize-quoting");
+        dir.write("track1.bin", [0; 2352]);
+        dir.write("track 2.bin", [0; 2352]);
+        let cue = dir.write(
+            "game.cue",
+            "FILE track1.bin BINARY\n TRACK 01 MODE1/2352\n  INDEX 01 00:00:00\n\
+             FILE 'track 2.bin' BINARY\n TRACK 02 AUDIO\n  INDEX 01 00:00:00\n",
+        );
+        normalize_cue(&cue.to_string_lossy(), None, false, false).unwrap();
+        assert_eq!(
+            fs::read_to_string(&cue).unwrap(),
+            "FILE \"track1.bin\" BINARY\n  TRACK 01 MODE1/2352\n    INDEX 01 00:00:00\n\
+             FILE \"track 2.bin\" BINARY\n  TRACK 02 AUDIO\n    INDEX 01 00:00:00\n"
+        );
+    }
+
+    #[test]
+    fn backslashes_are_replaced_when_normalizing_paths() {
+        let dir = TempDir::new("normalize-backslash");
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        dir.write("sub/game.bin", [0; 2352]);
+        let cuesheet = "FILE \"sub\\game.bin\" BINARY\n  TRACK 01 MODE1/2352\n    INDEX 01 00:00:00\n";
+        let cue = dir.write("game.cue", cuesheet);
+        let cue = cue.to_string_lossy();
+
+        let kept = normalize_cue(&cue, Some(&dir.path().join("kept.cue")), false, false).unwrap();
+        assert!(fs::read_to_string(kept).unwrap().starts_with("FILE \"sub\\game.bin\" BINARY\n"));
+        let normalized = normalize_cue(&cue, Some(&dir.path().join("normalized.cue")), false, true).unwrap();
+        let expected = format!("FILE \"sub{}game.bin\" BINARY\n", path::MAIN_SEPARATOR);
+        assert!(fs::read_to_string(normalized).unwrap().starts_with(&expected));
+    }
+
+    #[test]
+    fn tracks_are_sorted_and_numbered_in_order() {
+        let dir = TempDir::new("normalize-sorting");
+        dir.write("track1.bin", [0; 2352 * 3]);
+        dir.write("track4.bin", [0; 2352]);
+        let cue = dir.write(
+            "game.cue",
+            concat!(
+                "TITLE \"Game\"\n",
+                "FILE track4.bin BINARY\n  TRACK 04 AUDIO\n    INDEX 01 00:00:00\n",
+                "FILE track1.bin BINARY\n  TRACK 01 MODE1/2352\n    INDEX 01 00:00:00\n",
+                "  TRACK 03 AUDIO\n    INDEX 01 00:00:01\n  TRACK 02 AUDIO\n    INDEX 01 00:00:02\n",
+            ),
+        );
+        normalize_cue(&cue.to_string_lossy(), None, false, false).unwrap();
+        assert_eq!(
+            fs::read_to_string(&cue).unwrap(),
+            concat!(
+                "TITLE \"Game\"\n",
+                "FILE \"track1.bin\" BINARY\n  TRACK 01 MODE1/2352\n    INDEX 01 00:00:00\n",
+                "  TRACK 02 AUDIO\n    INDEX 01 00:00:01\n  TRACK 03 AUDIO\n    INDEX 01 00:00:02\n",
+                "FILE \"track4.bin\" BINARY\n  TRACK 04 AUDIO\n    INDEX 01 00:00:00\n",
+            )
+        );
+    }
+}
@@ -0,0 +1,51 @@
+use std::fs;
+use std::path::Path;
+use log::error;
+
+use crate::cue::{get_bin_from_cue, LineEnding};
+use crate::dir::{create_outdir, output_path, sanitize_filename};
+use crate::error::BinmergeError;
+use crate::merge::{merge_files_with, merged_format, write_merged_cue, MergeInput, MergeOptions};
+use crate::split::{split_bin_with, Trailing};
+
+/// Merges the bins of the cue sheet at `cue_path` and splits the merged bin back out into `outdir`, naming the track
+/// bins after `track_template` like [`split_bin_named`](crate::split_bin_named). Every track bin then starts at its
+/// first index, with its indexes worked out afresh from where the track lies on the whole disc, which repairs cue
+/// sheets whose bins don't start where their tracks do. Bins that aren't a whole number of sectors are padded with
+/// zero bytes so the tracks after them stay aligned.
+///
+/// The merged bin and cue sheet go in a scratch directory in `outdir`, which is removed afterwards whether or not
+/// the split worked. Returns `false` without writing anything when the scratch directory or a split file already
+/// exists.
+pub fn reindex(cue_path: &str, outdir: &Path, track_template: &str) -> Result<bool, BinmergeError> {
+    let mut bin_files = get_bin_from_cue(cue_path)?;
+    merged_format(&bin_files)?;
+    create_outdir(outdir)?;
+    let basename = sanitize_filename(&Path::new(cue_path).file_stem().unwrap_or_default().to_string_lossy());
+
+    let scratch = output_path(outdir, &format!(".{}.reindex", basename))?;
+    if scratch.exists() {
+        error!("Target scratch directory already exists: {}", scratch.display());
+        return Ok(false);
+    }
+    fs::create_dir(&scratch)?;
+
+    let merged_name = format!("{}.bin", basename);
+    let merged_bin = scratch.join(&merged_name);
+    let merged_cue = scratch.join(format!("{}.cue", basename));
+    let padding: Vec<u64> = bin_files.iter_mut().map(|bin_file| bin_file.pad_to_sector()).collect();
+    let files: Vec<MergeInput> = bin_files
+        .iter()
+        .zip(padding)
+        .map(|(bin_file, padding)| MergeInput { padding, ..MergeInput::new(&bin_file.filename) })
+        .collect();
+    let result = merge_files_with(&merged_bin, &files, MergeOptions::default())
+        .and_then(|_| {
+            let line_ending = LineEnding::detect(cue_path)?;
+            write_merged_cue(&bin_files, &merged_cue, &merged_name, false, line_ending)
+        })
+        .and_then(|_| split_bin_with(&merged_cue.to_string_lossy(), outdir, track_template, Trailing::Include, None));
+
+    fs::remove_dir_all(&scratch)?;
+    result
+}
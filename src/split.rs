@@ -0,0 +1,341 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::collections::HashSet;
+use std::path::Path;
+use lazy_static::lazy_static;
+use log::error;
+
+use regex::{Captures, Regex};
+
+use crate::cue::{get_bin_from_cue, push_track, BinFile, LineEnding};
+use crate::dir::{create_outdir, output_path, sanitize_filename};
+use crate::error::BinmergeError;
+use crate::merge::check_raw_format;
+
+lazy_static! {
+    static ref NUM_PATTERN: Regex = Regex::new(r"\{num(?::0(\d+))?\}").unwrap();
+}
+
+/// Track bin names used by [`split_bin`], e.g. `game (Track 01).bin`.
+pub const DEFAULT_TRACK_TEMPLATE: &str = "{base} (Track {num:02}).bin";
+
+/// What [`split_bin_with`] does with the bytes of a bin past the end of its last whole sector. The last track runs to
+/// the end of the bin, so that's all that can follow it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Trailing {
+    /// Keep the partial sector at the end of the last track's bin, which is then byte for byte the end of the input bin
+    Include,
+    /// Leave the partial sector out, so the last track's bin is whole sectors
+    Drop,
+    /// Fail before writing anything
+    Error,
+}
+
+// Fills in {base} and {num}, where {num:0N} pads the track number with zeros to N digits
+pub(crate) fn track_filename(template: &str, base: &str, num: u32) -> String {
+    let name = NUM_PATTERN.replace_all(template, |caps: &Captures| {
+        let width = caps.get(1).and_then(|width| width.as_str().parse().ok()).unwrap_or(0);
+        format!("{:0width$}", num, width = width)
+    });
+    name.replace("{base}", base)
+}
+
+fn copy_range(infile: &mut File, out_path: &Path, start: u64, length: u64) -> io::Result<()> {
+    infile.seek(SeekFrom::Start(start))?;
+    let mut outfile = OpenOptions::new().write(true).create_new(true).open(out_path)?;
+    io::copy(&mut infile.take(length), &mut outfile)?;
+    Ok(())
+}
+
+/// Parses a list of track numbers and ranges such as `2,5-7` into the track numbers it covers, in order.
+pub fn parse_track_list(list: &str) -> Result<Vec<u32>, String> {
+    let number = |num: &str| num.trim().parse::<u32>().map_err(|_| format!("invalid track number {} in {}", num, list));
+    let mut tracks = Vec::new();
+    for item in list.split(',') {
+        match item.split_once('-') {
+            Some((first, last)) => {
+                let (first, last) = (number(first)?, number(last)?);
+                if first > last {
+                    return Err(format!("track range {} runs backwards", item));
+                }
+                tracks.extend(first..=last);
+            }
+            None => tracks.push(number(item)?),
+        }
+    }
+    Ok(tracks)
+}
+
+/// Reads the data of track `track_num` of the cue sheet at `cue_path`, see [`write_track`].
+pub fn read_track(cue_path: &str, track_num: u32) -> Result<Vec<u8>, BinmergeError> {
+    let mut data = Vec::new();
+    write_track(cue_path, track_num, &mut data)?;
+    Ok(data)
+}
+
+/// Copies the data of track `track_num` of the cue sheet at `cue_path` to `out` without splitting the rest, and
+/// returns the number of bytes copied.
+///
+/// That's the same bytes [`split_bin`] gives the track: from its `INDEX 00` pregap if it has one, up to where the
+/// next track starts or, for the last track of a bin, the end of the bin. A `PREGAP` isn't stored in any bin so it
+/// isn't part of it. Fails when the cue sheet has no such track or its range runs past the end of its bin.
+pub fn write_track<W: Write>(cue_path: &str, track_num: u32, out: &mut W) -> Result<u64, BinmergeError> {
+    let bin_files = get_bin_from_cue(cue_path)?;
+    let found = bin_files.iter().find_map(|bin_file| {
+        let i = bin_file.tracks.iter().position(|track| track.num == track_num)?;
+        Some((bin_file, i))
+    });
+    match found {
+        Some((bin_file, i)) => Ok(copy_track(bin_file, i, out)?),
+        None => Err(format!("{} has no track {:02}", cue_path, track_num).into()),
+    }
+}
+
+// Start and end in bytes of the i-th track of bin_file, the last track running to the end of the bin
+pub(crate) fn track_extent(bin_file: &BinFile, i: usize) -> io::Result<(u64, u64)> {
+    let track = &bin_file.tracks[i];
+    let size = bin_file.size.unwrap_or(0);
+    let (start, end) = track.byte_range(track.sector_size);
+    let end = if i + 1 < bin_file.tracks.len() { end.unwrap_or(size) } else { size };
+    if start > end || end > size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("track {:02} runs past the end of {}", track.num, bin_file.filename.display()),
+        ));
+    }
+    Ok((start, end))
+}
+
+// Copies the i-th track of bin_file, the way write_track does
+pub(crate) fn copy_track<W: Write>(bin_file: &BinFile, i: usize, out: &mut W) -> io::Result<u64> {
+    let track_num = bin_file.tracks[i].num;
+    let (start, end) = track_extent(bin_file, i)?;
+    let mut infile = File::open(&bin_file.filename)?;
+    infile.seek(SeekFrom::Start(start))?;
+    let copied = io::copy(&mut infile.take(end - start), out)?;
+    // The bin was measured when the cue sheet was read, so a short read means it changed since
+    if copied != end - start {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("{} ended {} bytes into track {:02}", bin_file.filename.display(), copied, track_num),
+        ));
+    }
+    Ok(copied)
+}
+
+/// Splits every track of the cue sheet at `cue_path` into its own bin in `outdir`, along with a cue sheet referencing them.
+///
+/// `outdir` is created if it doesn't exist. The cue sheet keeps the line endings of the one at `cue_path`.
+pub fn split_bin(cue_path: &str, outdir: &Path) -> Result<bool, BinmergeError> {
+    split_bin_named(cue_path, outdir, DEFAULT_TRACK_TEMPLATE)
+}
+
+/// Like [`split_bin`], naming the track bins after `track_template`, where `{base}` is the name of the cue sheet
+/// and `{num}` the track number, padded with zeros to N digits by `{num:0N}`.
+///
+/// Fails before writing anything when two tracks would get the same name.
+pub fn split_bin_named(cue_path: &str, outdir: &Path, track_template: &str) -> Result<bool, BinmergeError> {
+    split_bin_with(cue_path, outdir, track_template, Trailing::Include, None)
+}
+
+/// Like [`split_bin_named`], dealing with a partial sector at the end of a bin as `trailing` says, and only writing
+/// the tracks numbered in `only_tracks` when it's set, see [`parse_track_list`]. The split cue sheet then only
+/// references those tracks.
+pub fn split_bin_with(
+    cue_path: &str,
+    outdir: &Path,
+    track_template: &str,
+    trailing: Trailing,
+    only_tracks: Option<&[u32]>,
+) -> Result<bool, BinmergeError> {
+    let bin_files = get_bin_from_cue(cue_path)?;
+    for bin_file in &bin_files {
+        check_raw_format(&bin_file.format, "split")?;
+    }
+    if let Some(only_tracks) = only_tracks {
+        let track_nums: Vec<u32> =
+            bin_files.iter().flat_map(|bin_file| &bin_file.tracks).map(|track| track.num).collect();
+        if let Some(missing) = only_tracks.iter().find(|num| !track_nums.contains(num)) {
+            return Err(format!("{} has no track {:02}", cue_path, missing).into());
+        }
+    }
+    create_outdir(outdir)?;
+    // Every file is named after the cue sheet, made safe so a crafted name can't write outside outdir
+    let basename = sanitize_filename(&Path::new(cue_path).file_stem().unwrap_or_default().to_string_lossy());
+
+    let split_cue = output_path(outdir, &format!("{}.cue", basename))?;
+    if split_cue.exists() {
+        error!("Target split cue path already exists: {}", split_cue.display());
+        return Ok(false);
+    }
+
+    let mut cuesheet = String::new();
+    let mut ranges = Vec::new();
+    let mut track_filenames = HashSet::new();
+    for bin_file in &bin_files {
+        for command in &bin_file.metadata {
+            cuesheet += &format!("{}\n", command);
+        }
+        for (i, track) in bin_file.tracks.iter().enumerate() {
+            if only_tracks.is_some_and(|only_tracks| !only_tracks.contains(&track.num)) {
+                continue;
+            }
+            // Each track starts at its first index so a pregap stays with the track it belongs to
+            let track_start = track.file_offset.unwrap_or(0);
+
+            let track_filename = track_filename(track_template, &basename, track.num);
+            if !track_filenames.insert(track_filename.clone()) {
+                return Err(format!("Track template gives more than one track the name {}", track_filename).into());
+            }
+            let track_path = output_path(outdir, &track_filename)?;
+            if track_path.exists() {
+                error!("Target track bin path already exists: {}", track_path.display());
+                return Ok(false);
+            }
+
+            cuesheet += &format!("FILE \"{}\" {}\n", track_filename, bin_file.format);
+            // An INDEX 00 pregap is stored in the bin, so it stays at the start of the track's own bin
+            let before_start = track.indexes.iter().find(|index| index.file_offset.checked_sub(track_start).is_none());
+            if let Some(index) = before_start {
+                let (num, id) = (track.num, index.id);
+                return Err(format!("track {:02} INDEX {:02} comes before the start of the track", num, id).into());
+            }
+            push_track(&mut cuesheet, track, |index| index.file_offset.saturating_sub(track_start));
+
+            // The last track's length isn't in the cue, so it runs to the end of the bin, partial sector and all
+            let (start, mut end) = track_extent(bin_file, i)?;
+            if i + 1 == bin_file.tracks.len() {
+                let whole_end = track.byte_range(track.sector_size).1.unwrap_or(end).min(end);
+                match trailing {
+                    Trailing::Error if end > whole_end => {
+                        return Err(format!(
+                            "{} has {} bytes past the last whole sector of track {:02}",
+                            bin_file.filename.display(),
+                            end - whole_end,
+                            track.num
+                        )
+                        .into());
+                    }
+                    Trailing::Drop => end = whole_end,
+                    Trailing::Include | Trailing::Error => {}
+                }
+            }
+            ranges.push((&bin_file.filename, track_path, start, end - start));
+        }
+    }
+
+    for (filename, track_path, start, length) in ranges {
+        let mut infile = File::open(filename)?;
+        copy_range(&mut infile, &track_path, start, length)?;
+    }
+
+    fs::write(split_cue, LineEnding::detect(cue_path)?.apply(&cuesheet))?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    // Two tracks of two sectors, with 100 bytes past the last whole sector
+    fn padded_bin(dir: &TempDir) -> String {
+        let data: Vec<u8> = (0..4 * 2352 + 100).map(|i| (i / 2352) as u8 + 1).collect();
+        dir.write("game.bin", data);
+        let cuesheet = concat!(
+            "FILE \"game.bin\" BINARY\n",
+            "  TRACK 01 MODE1/2352\n",
+            "    INDEX 01 00:00:00\n",
+            "  TRACK 02 AUDIO\n",
+            "    INDEX 01 00:00:02\n",
+        );
+        dir.write("game.cue", cuesheet).to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn trailing_data_is_kept_dropped_or_refused() {
+        let dir = TempDir::new("split-trailing");
+        let cue = padded_bin(&dir);
+        let split = |outdir: &str, trailing| {
+            split_bin_with(&cue, &dir.path().join(outdir), DEFAULT_TRACK_TEMPLATE, trailing, None)
+        };
+        let last_track = |outdir: &str| fs::read(dir.path().join(outdir).join("game (Track 02).bin")).unwrap();
+
+        assert!(split("include", Trailing::Include).unwrap());
+        let include = last_track("include");
+        assert_eq!(include.len(), 2 * 2352 + 100);
+        assert_eq!(include[2 * 2352..], [5; 100]);
+
+        assert!(split("drop", Trailing::Drop).unwrap());
+        assert_eq!(last_track("drop"), include[..2 * 2352]);
+        // Only the last track can have a partial sector after it
+        let first_track = fs::read(dir.path().join("drop/game (Track 01).bin")).unwrap();
+        assert_eq!(first_track, [vec![1; 2352], vec![2; 2352]].concat());
+
+        let err = split("error", Trailing::Error).unwrap_err();
+        assert!(err.to_string().contains("has 100 bytes past the last whole sector of track 02"), "{}", err);
+        let outdir = dir.path().join("error");
+        assert!(!outdir.join("game (Track 01).bin").exists());
+        assert!(!outdir.join("game (Track 02).bin").exists());
+    }
+
+    // Where a FILE points only decides what's read, every file written goes in the output directory
+    #[test]
+    fn split_only_writes_to_the_output_directory() {
+        let dir = TempDir::new("split-traversal");
+        fs::create_dir_all(dir.path().join("cues/game")).unwrap();
+        fs::create_dir(dir.path().join("bins")).unwrap();
+        dir.write("bins/x.bin", vec![1; 2 * 2352]);
+        let cuesheet = "FILE \"../../bins/x.bin\" BINARY\n  TRACK 01 MODE1/2352\n    INDEX 01 00:00:00\n";
+        let cue = dir.write("cues/game/game.cue", cuesheet).to_string_lossy().into_owned();
+        let outdir = dir.path().join("cues/game/out");
+
+        assert!(split_bin(&cue, &outdir).unwrap());
+        let listing = |subdir: &str| {
+            let mut names: Vec<String> = fs::read_dir(dir.path().join(subdir))
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+                .collect();
+            names.sort();
+            names
+        };
+        assert_eq!(listing("cues/game/out"), ["game (Track 01).bin", "game.cue"]);
+        assert_eq!(fs::read(outdir.join("game (Track 01).bin")).unwrap(), [1; 2 * 2352]);
+        assert_eq!(listing("bins"), ["x.bin"]);
+        assert_eq!(listing("cues/game"), ["game.cue", "out"]);
+
+        // A track template can't lead out of the output directory either
+        let outdir = dir.path().join("cues/game/template");
+        assert!(split_bin_named(&cue, &outdir, "../../bins/{num}.bin").is_err());
+        assert_eq!(listing("bins"), ["x.bin"]);
+        assert!(!outdir.join("game.cue").exists());
+    }
+
+    #[test]
+    fn single_track_is_read_with_its_pregap() {
+        let dir = TempDir::new("read-track");
+        let data: Vec<u8> = (0..5 * 2352).map(|i| (i % 251) as u8).collect();
+        dir.write("game.bin", &data);
+        let cuesheet = concat!(
+            "FILE \"game.bin\" BINARY\n",
+            "  TRACK 01 MODE1/2352\n",
+            "    INDEX 01 00:00:00\n",
+            "  TRACK 02 AUDIO\n",
+            "    INDEX 00 00:00:02\n",
+            "    INDEX 01 00:00:03\n",
+        );
+        let cue = dir.write("game.cue", cuesheet).to_string_lossy().into_owned();
+
+        assert_eq!(read_track(&cue, 2).unwrap(), data[2 * 2352..]);
+        assert_eq!(read_track(&cue, 1).unwrap(), data[..2 * 2352]);
+        let mut out = Vec::new();
+        assert_eq!(write_track(&cue, 2, &mut out).unwrap(), 3 * 2352);
+        assert_eq!(out, data[2 * 2352..]);
+        // The same bytes split_bin gives the track
+        assert!(split_bin(&cue, &dir.path().join("split")).unwrap());
+        assert_eq!(fs::read(dir.path().join("split/game (Track 02).bin")).unwrap(), out);
+
+        let err = read_track(&cue, 3).unwrap_err();
+        assert!(err.to_string().ends_with("has no track 03"), "{}", err);
+    }
+}
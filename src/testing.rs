@@ -0,0 +1,35 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+// A scratch directory for a unit test, removed along with everything in it once the test is done
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+    // Named after the test so that tests running at the same time each get their own
+    pub(crate) fn new(name: &str) -> TempDir {
+        let path = env::temp_dir().join(format!("binmerge-{}-{}", process::id(), name));
+        if path.exists() {
+            fs::remove_dir_all(&path).unwrap();
+        }
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub(crate) fn write(&self, name: &str, contents: impl AsRef<[u8]>) -> PathBuf {
+        let path = self.0.join(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
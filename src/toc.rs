@@ -0,0 +1,453 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead};
+use std::path::{Path, PathBuf};
+
+use crate::cue::{
+    bin_path, check_bin_files, cuestamp_to_sectors, open_bin_file, sector_size, sectors_to_cuestamp, BinFile,
+    CueLineError, CueParseError, Index, ParseOptions, Track,
+};
+use crate::error::BinmergeError;
+
+// A track as the TOC file describes it, a stretch of silence followed by a range of one file, laid out as indexes
+// once the whole track has been read
+struct TocTrack {
+    track: Track,
+    line_number: usize,
+    line_text: String,
+    filename: Option<String>,
+    data_start: u64,
+    data_end: Option<u64>,
+    silence: u32,
+    start: Option<u32>,
+    indexes: Vec<u32>,
+}
+
+impl TocTrack {
+    fn invalid(&self, kind: CueLineError) -> CueParseError {
+        CueParseError::InvalidLine { line_number: self.line_number, line_text: self.line_text.clone(), kind }
+    }
+
+    // Sectors from the start of the track to the end of its data so far, which START defaults to
+    fn length(&self) -> Option<u32> {
+        let sector_size = self.track.sector_size as u64;
+        match (&self.filename, self.data_end) {
+            (None, _) => Some(self.silence),
+            (Some(_), Some(end)) => Some(self.silence + ((end - self.data_start) / sector_size) as u32),
+            (Some(_), None) => None,
+        }
+    }
+}
+
+/// Whether `path` names a cdrdao TOC file, going by its `.toc` extension.
+pub fn is_toc(path: &str) -> bool {
+    Path::new(path).extension().is_some_and(|extension| extension.eq_ignore_ascii_case("toc"))
+}
+
+/// Parses a cdrdao TOC file into the bin files it references, the same way [`get_bin_from_cue_with`] does for a cue
+/// sheet, which reads `.toc` files through this.
+///
+/// The tracks' `FILE`, `AUDIOFILE` and `DATAFILE` data, `SILENCE`, `ZERO`, `PREGAP`, `START` and `INDEX` are laid out
+/// as cue sheet indexes, `CATALOG` and `ISRC` are kept as commands and the copy, pre-emphasis and four channel flags
+/// as `FLAGS`. A track's data has to be one range of one file that starts on a sector boundary, with any silence
+/// before it, since that's all a cue sheet can describe. Sub-channel data isn't supported, and audio samples are kept
+/// in the byte order they're stored in.
+///
+/// [`get_bin_from_cue_with`]: crate::get_bin_from_cue_with
+pub fn get_bin_from_toc(
+    toc_path: &str,
+    options: &ParseOptions,
+) -> Result<(Vec<BinFile>, Vec<PathBuf>), BinmergeError> {
+    let reader = io::BufReader::new(File::open(toc_path).map_err(CueParseError::Io)?);
+    let toc_dir = match options.basedir {
+        Some(basedir) => basedir,
+        None => Path::new(toc_path).parent().unwrap_or(Path::new(".")),
+    };
+    Ok(parse_toc_lines(reader, toc_dir, options)?)
+}
+
+// Cue sheet track type of a cdrdao track mode, which has the same sector size
+fn track_type(mode: &str) -> Result<&'static str, CueParseError> {
+    match mode {
+        "AUDIO" => Ok("AUDIO"),
+        "MODE1" => Ok("MODE1/2048"),
+        "MODE1_RAW" => Ok("MODE1/2352"),
+        "MODE2" | "MODE2_FORM_MIX" => Ok("MODE2/2336"),
+        "MODE2_FORM1" => Ok("MODE2/2048"),
+        "MODE2_FORM2" => Ok("MODE2/2324"),
+        "MODE2_RAW" => Ok("MODE2/2352"),
+        _ => Err(CueParseError::UnknownTrackType(mode.to_string())),
+    }
+}
+
+// Words of a line up to any // comment, with quoted strings kept whole along with their quotes
+fn words(line: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut rest = line.trim_start();
+    while !rest.is_empty() && !rest.starts_with("//") {
+        let end = if let Some(quoted) = rest.strip_prefix('"') {
+            let mut escaped = false;
+            let closing = quoted.find(|c| {
+                let closes = c == '"' && !escaped;
+                escaped = c == '\\' && !escaped;
+                closes
+            });
+            closing.map_or(rest.len(), |closing| closing + 2)
+        } else {
+            rest.find(char::is_whitespace).unwrap_or(rest.len())
+        };
+        words.push(&rest[..end]);
+        rest = rest[end..].trim_start();
+    }
+    words
+}
+
+fn unquote(word: &str) -> &str {
+    word.strip_prefix('"').and_then(|word| word.strip_suffix('"')).unwrap_or(word)
+}
+
+// A length or position as MSF, or as a number of 4 byte stereo samples like cdrdao accepts for audio, in bytes
+fn toc_bytes(word: &str, sector_size: u32) -> Result<u64, CueLineError> {
+    if word.contains(':') {
+        let sectors = cuestamp_to_sectors(word).map_err(CueLineError::InvalidTimestamp)?;
+        Ok(sectors as u64 * sector_size as u64)
+    } else {
+        word.parse::<u64>().map(|samples| samples * 4).map_err(|_| CueLineError::InvalidTimestamp("not MSF or samples"))
+    }
+}
+
+fn toc_sectors(word: &str) -> Result<u32, CueLineError> {
+    cuestamp_to_sectors(word).map_err(CueLineError::InvalidTimestamp)
+}
+
+fn parse_toc_lines<R: BufRead>(
+    reader: R,
+    toc_dir: &Path,
+    options: &ParseOptions,
+) -> Result<(Vec<BinFile>, Vec<PathBuf>), CueParseError> {
+    let mut tracks: Vec<TocTrack> = Vec::new();
+    let mut disc_metadata: Vec<String> = Vec::new();
+    // Where the data read from each file so far ends, which a DATAFILE without an offset carries on from
+    let mut file_ends: HashMap<String, Option<u64>> = HashMap::new();
+    let mut cd_text_depth = 0;
+
+    for (line_index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = if line_index == 0 { line.trim_start_matches('\u{feff}') } else { &line }.trim_end().to_string();
+        let invalid_line = |kind| CueParseError::InvalidLine {
+            line_number: line_index + 1,
+            line_text: line.clone(),
+            kind,
+        };
+        let words = words(&line);
+        let first = match words.first() {
+            Some(first) => first,
+            None => continue,
+        };
+
+        // CD-TEXT blocks say nothing about where the data is
+        if cd_text_depth > 0 || first.eq_ignore_ascii_case("CD_TEXT") {
+            for word in &words {
+                match *word {
+                    "{" => cd_text_depth += 1,
+                    "}" => cd_text_depth -= 1,
+                    _ => {}
+                }
+            }
+            continue;
+        }
+
+        let keyword = first.to_ascii_uppercase();
+        let args = &words[1..];
+        let missing_argument = || invalid_line(CueLineError::InvalidStatement("missing argument"));
+        let arg = |i: usize| args.get(i).copied().ok_or_else(missing_argument);
+        match keyword.as_str() {
+            "CD_DA" | "CD_ROM" | "CD_ROM_XA" | "CD_I" => continue,
+            "CATALOG" => {
+                disc_metadata.push(format!("CATALOG {}", unquote(arg(0)?)));
+                continue;
+            }
+            "TRACK" => {
+                let mode = arg(0)?.to_ascii_uppercase();
+                if args.len() > 1 {
+                    return Err(invalid_line(CueLineError::Unsupported("sub-channel data")));
+                }
+                let track_type = track_type(&mode)?.to_string();
+                let track_sector_size = sector_size(&track_type)?;
+                tracks.push(TocTrack {
+                    track: Track::new(tracks.len() as u32 + 1, track_type, track_sector_size),
+                    line_number: line_index + 1,
+                    line_text: line.clone(),
+                    filename: None,
+                    data_start: 0,
+                    data_end: None,
+                    silence: 0,
+                    start: None,
+                    indexes: Vec::new(),
+                });
+                continue;
+            }
+            _ => {}
+        }
+
+        let toc_track = match tracks.last_mut() {
+            Some(toc_track) => toc_track,
+            None => return Err(invalid_line(CueLineError::InvalidStatement("outside of a track"))),
+        };
+        let sector_size = toc_track.track.sector_size;
+        match keyword.as_str() {
+            "NO" if args.len() == 1 && matches!(args[0].to_ascii_uppercase().as_str(), "COPY" | "PRE_EMPHASIS") => {}
+            "TWO_CHANNEL_AUDIO" => {}
+            "COPY" => toc_track.track.flags.push("DCP".to_string()),
+            "PRE_EMPHASIS" => toc_track.track.flags.push("PRE".to_string()),
+            "FOUR_CHANNEL_AUDIO" => toc_track.track.flags.push("4CH".to_string()),
+            "ISRC" => toc_track.track.metadata.push(format!("ISRC {}", unquote(arg(0)?))),
+            "SILENCE" | "ZERO" | "PREGAP" => {
+                // ZERO can name a mode before its length, which doesn't change how long it is
+                let length = toc_sectors(arg(args.len().saturating_sub(1))?).map_err(invalid_line)?;
+                if toc_track.filename.is_some() {
+                    return Err(invalid_line(CueLineError::Unsupported("silence after a track's file data")));
+                }
+                toc_track.silence += length;
+                if keyword == "PREGAP" {
+                    toc_track.start = Some(toc_track.silence);
+                }
+            }
+            "FILE" | "AUDIOFILE" | "DATAFILE" => {
+                let filename = unquote(arg(0)?).to_string();
+                let mut rest = &args[1..];
+                let offset = match rest.first().and_then(|word| word.strip_prefix('#')) {
+                    Some(offset) => {
+                        rest = &rest[1..];
+                        let offset = offset.parse::<u64>();
+                        Some(offset.map_err(|_| invalid_line(CueLineError::InvalidStatement("invalid byte offset")))?)
+                    }
+                    None => None,
+                };
+                // DATAFILE carries on where the file's data left off, FILE gives where in the file to start
+                let (start, length) = if keyword == "DATAFILE" {
+                    let start = match (offset, file_ends.get(&filename)) {
+                        (Some(offset), _) => offset,
+                        (None, Some(Some(end))) => *end,
+                        (None, Some(None)) => {
+                            return Err(invalid_line(CueLineError::Unsupported("data after data that runs to EOF")))
+                        }
+                        (None, None) => 0,
+                    };
+                    (start, rest.first())
+                } else {
+                    let start = rest.first().ok_or_else(missing_argument)?;
+                    let start = toc_bytes(start, sector_size).map_err(invalid_line)?;
+                    (offset.unwrap_or(0) + start, rest.get(1))
+                };
+                let end = match length {
+                    Some(length) => Some(start + toc_bytes(length, sector_size).map_err(invalid_line)?),
+                    None => None,
+                };
+                file_ends.insert(filename.clone(), end);
+
+                match &toc_track.filename {
+                    None if start % sector_size as u64 != 0 => {
+                        return Err(invalid_line(CueLineError::Unsupported("data that doesn't start on a sector")))
+                    }
+                    None => {
+                        toc_track.filename = Some(filename);
+                        toc_track.data_start = start;
+                    }
+                    Some(track_filename) if *track_filename == filename && toc_track.data_end == Some(start) => {}
+                    Some(_) => {
+                        return Err(invalid_line(CueLineError::Unsupported("track data split across files or ranges")))
+                    }
+                }
+                toc_track.data_end = end;
+            }
+            "START" => {
+                let start = match (args.first(), toc_track.length()) {
+                    (Some(word), _) => toc_sectors(word).map_err(invalid_line)?,
+                    (None, Some(length)) => length,
+                    (None, None) => {
+                        return Err(invalid_line(CueLineError::Unsupported("START after data of unknown length")))
+                    }
+                };
+                toc_track.start = Some(start);
+            }
+            "INDEX" => toc_track.indexes.push(toc_sectors(arg(0)?).map_err(invalid_line)?),
+            _ => return Err(invalid_line(CueLineError::Unsupported("this statement"))),
+        }
+    }
+
+    let mut bin_files: Vec<BinFile> = Vec::new();
+    let mut missing_bin_files: Vec<(PathBuf, io::Error)> = Vec::new();
+    for toc_track in tracks {
+        let filename = match &toc_track.filename {
+            Some(filename) => filename.clone(),
+            None => return Err(toc_track.invalid(CueLineError::Unsupported("a track without file data"))),
+        };
+        let data_start = (toc_track.data_start / toc_track.track.sector_size as u64) as u32;
+
+        // Silence is the cue sheet's PREGAP, what's left before START is an INDEX 00 pregap stored in the file
+        let index_01 = match toc_track.start {
+            Some(start) if start < toc_track.silence => {
+                return Err(toc_track.invalid(CueLineError::Unsupported("START within the silence of a track")))
+            }
+            Some(start) => data_start + (start - toc_track.silence),
+            None if toc_track.silence > 0 => {
+                return Err(toc_track.invalid(CueLineError::Unsupported("silence before a track's START")))
+            }
+            None => data_start,
+        };
+        let mut track = toc_track.track;
+        if index_01 > data_start {
+            track.indexes.push(Index::new(0, sectors_to_cuestamp(data_start), data_start));
+        }
+        if toc_track.silence > 0 {
+            track.pregap = Some(sectors_to_cuestamp(toc_track.silence));
+        }
+        track.indexes.push(Index::new(1, sectors_to_cuestamp(index_01), index_01));
+        for (id, index) in (2..).zip(&toc_track.indexes) {
+            let file_offset = index_01 + index;
+            track.indexes.push(Index::new(id, sectors_to_cuestamp(file_offset), file_offset));
+        }
+
+        // Consecutive tracks of the same file share a bin like consecutive tracks under a FILE do
+        if bin_files.last().is_none_or(|bin_file| bin_file.cue_filename != filename) {
+            let format = if Path::new(&filename).extension().is_some_and(|ext| ext.eq_ignore_ascii_case("wav")) {
+                "WAVE"
+            } else {
+                "BINARY"
+            };
+            let bin_file_path = bin_path(toc_dir, &filename, options.remap);
+            let mut bin_file = open_bin_file(bin_file_path, format.to_string(), &mut missing_bin_files);
+            bin_file.cue_filename = filename;
+            if bin_files.is_empty() {
+                bin_file.metadata = std::mem::take(&mut disc_metadata);
+            }
+            bin_files.push(bin_file);
+        }
+        if let Some(bin_file) = bin_files.last_mut() {
+            bin_file.tracks.push(track);
+        }
+    }
+
+    let zero_filled = check_bin_files(&mut bin_files, missing_bin_files, options)?;
+    Ok((bin_files, zero_filled))
+}
+
+#[cfg(all(test, feature = "toc"))]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn indexes(track: &Track) -> Vec<(u32, u32)> {
+        track.indexes.iter().map(|index| (index.id, index.file_offset)).collect()
+    }
+
+    fn parse_error(toc: &str) -> CueParseError {
+        match parse_toc_lines(toc.as_bytes(), Path::new("missing"), &ParseOptions::default()) {
+            Err(err) => err,
+            Ok(_) => panic!("parsed {:?}", toc),
+        }
+    }
+
+    #[test]
+    fn toc_files_are_named_by_their_extension() {
+        assert!(is_toc("game.toc"));
+        assert!(is_toc("dir/game.TOC"));
+        assert!(!is_toc("game.cue"));
+        assert!(!is_toc("toc"));
+    }
+
+    #[test]
+    fn datafile_and_file_ranges_of_one_bin() {
+        let dir = TempDir::new("toc-ranges");
+        dir.write("game.bin", vec![0; 20 * 2352]);
+        let toc = concat!(
+            "CD_ROM\n",
+            "CATALOG \"0123456789012\"\n",
+            "// The data track, then a second one carrying on where its data ends\n",
+            "TRACK MODE1_RAW\n",
+            "COPY\n",
+            "DATAFILE \"game.bin\" #0 00:00:10\n",
+            "TRACK MODE1_RAW\n",
+            "DATAFILE \"game.bin\" 00:00:05\n",
+            "TRACK AUDIO\n",
+            "ISRC \"USABC1234567\"\n",
+            "SILENCE 00:01:00\n",
+            "ZERO AUDIO 00:01:00\n",
+            "FILE \"game.bin\" #35280 0 00:00:05\n",
+            "START 00:02:00\n",
+        );
+        let toc = dir.write("game.toc", toc);
+        let (bin_files, zero_filled) = get_bin_from_toc(&toc.to_string_lossy(), &ParseOptions::default()).unwrap();
+
+        assert!(zero_filled.is_empty());
+        assert_eq!(bin_files.len(), 1);
+        let bin_file = &bin_files[0];
+        assert_eq!(bin_file.filename, dir.path().join("game.bin"));
+        assert_eq!(bin_file.format, "BINARY");
+        assert_eq!(bin_file.metadata, ["CATALOG 0123456789012"]);
+
+        let tracks = &bin_file.tracks;
+        assert_eq!(tracks.iter().map(|track| track.num).collect::<Vec<_>>(), [1, 2, 3]);
+        assert_eq!(tracks[0].track_type, "MODE1/2352");
+        assert_eq!(tracks[0].flags, ["DCP"]);
+        assert_eq!(indexes(&tracks[0]), [(1, 0)]);
+        assert_eq!(indexes(&tracks[1]), [(1, 10)]);
+        assert_eq!(tracks[2].track_type, "AUDIO");
+        assert_eq!(tracks[2].metadata, ["ISRC USABC1234567"]);
+        // SILENCE and ZERO aren't in the bin, so they add up to a PREGAP before the data
+        assert_eq!(tracks[2].pregap.as_deref(), Some("00:02:00"));
+        assert_eq!(indexes(&tracks[2]), [(1, 15)]);
+    }
+
+    #[test]
+    fn start_and_index_of_a_bin_in_another_directory() {
+        let dir = TempDir::new("toc-start-index");
+        fs::create_dir(dir.path().join("bins")).unwrap();
+        dir.write("bins/audio.bin", vec![0; 375 * 2352]);
+        let toc = concat!(
+            "CD_DA\n",
+            "TRACK AUDIO\n",
+            "FILE \"bins/audio.bin\" 0 00:05:00\n",
+            "START 00:02:00\n",
+            "INDEX 00:01:00\n",
+        );
+        let toc = dir.write("game.toc", toc);
+        let (bin_files, _) = get_bin_from_toc(&toc.to_string_lossy(), &ParseOptions::default()).unwrap();
+
+        // Relative to the TOC file, and listed the way the TOC file names it
+        assert_eq!(bin_files[0].filename, dir.path().join("bins/audio.bin"));
+        assert_eq!(bin_files[0].cue_filename, "bins/audio.bin");
+        // The data before START is a pregap stored in the bin, INDEX counts from START
+        let track = &bin_files[0].tracks[0];
+        assert_eq!(track.pregap, None);
+        assert_eq!(indexes(track), [(0, 0), (1, 150), (2, 225)]);
+        assert_eq!(track.indexes[2].stamp, "00:03:00");
+    }
+
+    #[test]
+    fn malformed_lines_are_refused_with_their_line_number() {
+        let err = parse_error("CD_DA\nTRACK AUDIO\nFILE \"audio.bin\" 0\nSIZE 12\n");
+        assert!(matches!(
+            err,
+            CueParseError::InvalidLine { line_number: 4, kind: CueLineError::Unsupported("this statement"), .. }
+        ));
+
+        let err = parse_error("CD_DA\nTRACK AUDIO\nFILE \"audio.bin\" 0 00:05:00\nSTART 00:61:00\n");
+        assert!(matches!(
+            err,
+            CueParseError::InvalidLine { line_number: 4, kind: CueLineError::InvalidTimestamp(_), .. }
+        ));
+
+        let err = parse_error("CD_DA\nFILE \"audio.bin\" 0\n");
+        assert!(matches!(
+            err,
+            CueParseError::InvalidLine { line_number: 2, kind: CueLineError::InvalidStatement(reason), .. }
+                if reason == "outside of a track"
+        ));
+
+        assert!(matches!(parse_error("TRACK MODE3\n"), CueParseError::UnknownTrackType(mode) if mode == "MODE3"));
+    }
+}
@@ -0,0 +1,343 @@
+use log::warn;
+
+use crate::cue::{
+    cross_check_cue, get_bin_from_cue, sectors_to_cuestamp, total_length, total_sectors, BinFile, DiscMode,
+};
+use crate::error::BinmergeError;
+
+// What's wrong with the i-th track of bin_file, nothing when it lies within its bin
+fn track_problems(bin_file: &BinFile, i: usize) -> Vec<String> {
+    let size = bin_file.size.unwrap_or(0);
+    let track = &bin_file.tracks[i];
+    let mut problems = Vec::new();
+
+    // The index stamps are whole sectors, so only their distance from the end of the bin can be wrong
+    for index in &track.indexes {
+        if index.file_offset as u64 * track.sector_size as u64 > size {
+            problems.push(format!("INDEX {:02} past end of bin", index.id));
+        }
+    }
+    match track.byte_range(track.sector_size).1 {
+        Some(end) if end > size => problems.push(format!("ends {} bytes past end of bin", end - size)),
+        Some(_) => {}
+        None if track.indexes.is_empty() => problems.push("has no INDEX".to_string()),
+        None if i + 1 == bin_file.tracks.len() => problems.push("starts past end of bin".to_string()),
+        None => problems.push("next track has no INDEX".to_string()),
+    }
+    problems
+}
+
+// Bytes of bin_file taken up by the tracks that end within it
+fn tracks_size(bin_file: &BinFile) -> u64 {
+    let size = bin_file.size.unwrap_or(0);
+    bin_file
+        .tracks
+        .iter()
+        .map(|track| track.byte_range(track.sector_size))
+        .filter_map(|(start, end)| end.filter(|&end| end <= size).map(|end| end - start))
+        .sum()
+}
+
+// Everything verify_bin checks, worked out once for both the text and the JSON report
+struct VerifyReport {
+    bin_files: Vec<BinFile>,
+    disc_mode: DiscMode,
+    // Problems of every track of every bin, in the same order as their tracks
+    problems: Vec<Vec<Vec<String>>>,
+    // Bytes of every bin taken up by its tracks
+    tracks_sizes: Vec<u64>,
+    total_sectors: u32,
+    // Worth knowing about the whole disc, but only "No tracks" fails the check
+    notes: Vec<&'static str>,
+    // What rcue reads differently, or why it couldn't read the cue sheet, and None when it can't be reread
+    cross_check: Option<Result<Vec<String>, String>>,
+    valid: bool,
+}
+
+fn verify_report(cue_path: &str) -> Result<VerifyReport, BinmergeError> {
+    let bin_files = get_bin_from_cue(cue_path)?;
+    let track_types = bin_files.iter().flat_map(|bin_file| &bin_file.tracks).map(|track| track.track_type.as_str());
+    let disc_mode = DiscMode::from_track_types(track_types);
+
+    let problems: Vec<Vec<Vec<String>>> = bin_files
+        .iter()
+        .map(|bin_file| (0..bin_file.tracks.len()).map(|i| track_problems(bin_file, i)).collect())
+        .collect();
+    let tracks_sizes: Vec<u64> = bin_files.iter().map(tracks_size).collect();
+    let mut valid = problems.iter().flatten().all(Vec::is_empty)
+        && bin_files.iter().zip(&tracks_sizes).all(|(bin_file, &size)| size == bin_file.size.unwrap_or(0));
+
+    let total_sectors = total_sectors(&bin_files);
+    let mut notes = Vec::new();
+    // Past 80 minutes it only fits an overburned disc, which is more often a sign of a bad cue sheet
+    if total_sectors > 80 * 60 * 75 {
+        notes.push("Longer than an 80 minute disc");
+    }
+    // Homebrew images can go past 99 tracks, which parse and merge fine but can't be burned as they are
+    let track_count = bin_files.iter().map(|bin_file| bin_file.tracks.len()).sum::<usize>();
+    if track_count > 99 {
+        notes.push("More than the 99 tracks a disc can hold");
+    }
+    // Parsing already refuses a cue sheet without tracks, this keeps verify from ever calling one OK
+    if track_count == 0 {
+        notes.push("No tracks");
+        valid = false;
+    }
+
+    // A second opinion from rcue on how the cue sheet reads, which only needs a look when they differ. rcue only
+    // reads cue sheets from a file
+    let rereadable = cue_path != "-";
+    #[cfg(feature = "toc")]
+    let rereadable = rereadable && !crate::toc::is_toc(cue_path);
+    let cross_check = rereadable.then(|| cross_check_cue(cue_path, &bin_files).map_err(|e| e.to_string()));
+
+    Ok(VerifyReport { bin_files, disc_mode, problems, tracks_sizes, total_sectors, notes, cross_check, valid })
+}
+
+/// Checks that the bins of the cue sheet at `cue_path` hold exactly the tracks it describes, printing the byte range
+/// of every track.
+///
+/// Fails the check when a bin's size doesn't match the sum of its track lengths, when an index lies past the end of
+/// its bin, or when a track ends past the end of its bin or after the next track starts.
+pub fn verify_bin(cue_path: &str) -> Result<bool, BinmergeError> {
+    let report = verify_report(cue_path)?;
+    println!("Disc mode: {}", report.disc_mode);
+
+    for (i, bin_file) in report.bin_files.iter().enumerate() {
+        let size = bin_file.size.unwrap_or(0);
+        println!("{} ({} bytes)", bin_file.filename.display(), size);
+        println!("  {:<5} {:<10} {:>12} {:>12} {:>12}  Status", "Track", "Type", "Start", "End", "Bytes");
+
+        for (track, problems) in bin_file.tracks.iter().zip(&report.problems[i]) {
+            let (start, end) = track.byte_range(track.sector_size);
+            println!(
+                "  {:<5} {:<10} {:>12} {:>12} {:>12}  {}",
+                format!("{:02}", track.num),
+                track.track_type,
+                start,
+                end.map_or("?".to_string(), |end| end.to_string()),
+                end.map_or("?".to_string(), |end| end.saturating_sub(start).to_string()),
+                if problems.is_empty() { "OK".to_string() } else { problems.join(", ") },
+            );
+        }
+
+        if report.tracks_sizes[i] != size {
+            println!("  Tracks add up to {} bytes but the bin is {} bytes", report.tracks_sizes[i], size);
+        }
+    }
+
+    println!("Total: {} sectors ({})", report.total_sectors, total_length(&report.bin_files));
+    for note in &report.notes {
+        println!("{}", note);
+    }
+    match &report.cross_check {
+        Some(Ok(differences)) => {
+            for difference in differences {
+                warn!("{}", difference);
+            }
+        }
+        Some(Err(e)) => warn!("rcue can't parse {}: {}", cue_path, e),
+        None => {}
+    }
+
+    println!("{}", if report.valid { "OK" } else { "FAILED" });
+    Ok(report.valid)
+}
+
+/// Prints a tab separated table of where every track of the cue sheet at `cue_path` lies on the whole disc, as if
+/// its bins were merged: track number, type, start sector, length in sectors, byte offset and length in bytes.
+///
+/// A length that can't be worked out from the cue sheet and bin sizes is printed as `?`.
+pub fn print_sector_report(cue_path: &str) -> Result<bool, BinmergeError> {
+    let bin_files = get_bin_from_cue(cue_path)?;
+
+    println!("track\ttype\tstart_sector\tsectors\tbyte_offset\tbytes");
+    let (mut disc_sector, mut disc_byte) = (0u64, 0u64);
+    for bin_file in &bin_files {
+        for track in bin_file.iter_tracks() {
+            let (start, end) = track.byte_range(track.sector_size);
+            println!(
+                "{:02}\t{}\t{}\t{}\t{}\t{}",
+                track.num,
+                track.track_type,
+                disc_sector + track.file_offset.unwrap_or(0) as u64,
+                track.sectors.map_or("?".to_string(), |sectors| sectors.to_string()),
+                disc_byte + start,
+                end.map_or("?".to_string(), |end| end.saturating_sub(start).to_string()),
+            );
+        }
+        disc_sector += bin_file.sectors() as u64;
+        disc_byte += bin_file.size.unwrap_or(0);
+    }
+    Ok(true)
+}
+
+/// Prints a tab separated table of the gap between the `INDEX 01` of every track and that of the next track, or the
+/// end of the disc for the last one, as if the bins were merged: track, next track, gap in sectors, gap as `MM:SS:FF`
+/// and a status.
+///
+/// A gap that's negative or longer than an 80 minute disc is most likely a misparsed stamp or the wrong bin, and fails
+/// the check. A gap shorter than the 4 seconds the Red Book allows for a track is only noted.
+pub fn print_gap_report(cue_path: &str) -> Result<bool, BinmergeError> {
+    let bin_files = get_bin_from_cue(cue_path)?;
+
+    let mut starts = Vec::new();
+    let mut disc_sector = 0i64;
+    for bin_file in &bin_files {
+        for track in bin_file.iter_tracks() {
+            let start = track.index(1).map_or(0, |index| index.file_offset);
+            starts.push((format!("{:02}", track.num), disc_sector + start as i64));
+        }
+        disc_sector += bin_file.sectors() as i64;
+    }
+    starts.push(("end".to_string(), disc_sector));
+
+    let mut valid = true;
+    println!("track\tnext_track\tsectors\tlength\tstatus");
+    for pair in starts.windows(2) {
+        let gap = pair[1].1 - pair[0].1;
+        let status = if gap < 0 {
+            "negative"
+        } else if gap > 80 * 60 * 75 {
+            "longer than a disc"
+        } else if gap < 4 * 75 {
+            "shorter than 4 seconds"
+        } else {
+            "OK"
+        };
+        valid &= !matches!(status, "negative" | "longer than a disc");
+        let length = sectors_to_cuestamp(gap.unsigned_abs().min(u32::MAX as u64) as u32);
+        let sign = if gap < 0 { "-" } else { "" };
+        println!("{}\t{}\t{}\t{}{}\t{}", pair[0].0, pair[1].0, gap, sign, length, status);
+    }
+    Ok(valid)
+}
+
+/// Prints every track of the cue sheet at `cue_path` as if the bins were merged: track, type, start and length as
+/// `MM:SS:FF`, and the bin it's stored in as the cue sheet lists it. The start is where the track's data starts, at its
+/// `INDEX 00` if it has a pregap. The columns are tab separated, or comma separated CSV with `csv`.
+pub fn print_track_list(cue_path: &str, csv: bool) -> Result<bool, BinmergeError> {
+    let bin_files = get_bin_from_cue(cue_path)?;
+
+    let separator = if csv { "," } else { "\t" };
+    let field = |value: &str| if csv { csv_field(value) } else { value.to_string() };
+    println!("{}", ["track", "type", "start", "length", "file"].join(separator));
+    let mut disc_sector = 0u32;
+    for bin_file in &bin_files {
+        for track in bin_file.iter_tracks() {
+            let start = disc_sector.saturating_add(track.file_offset.unwrap_or(0));
+            let row = [
+                format!("{:02}", track.num),
+                field(&track.track_type),
+                sectors_to_cuestamp(start),
+                track.sectors.map_or("?".to_string(), sectors_to_cuestamp),
+                field(&bin_file.cue_filename),
+            ];
+            println!("{}", row.join(separator));
+        }
+        disc_sector = disc_sector.saturating_add(bin_file.sectors());
+    }
+    Ok(true)
+}
+
+// A CSV field, quoted when it holds a comma, quote or line break, with its quotes doubled
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+// A JSON string literal, escaping what JSON requires
+fn json_string(s: &str) -> String {
+    let mut json = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => json += "\\\"",
+            '\\' => json += "\\\\",
+            '\n' => json += "\\n",
+            '\r' => json += "\\r",
+            '\t' => json += "\\t",
+            c if c.is_control() => json += &format!("\\u{:04x}", c as u32),
+            c => json.push(c),
+        }
+    }
+    json + "\""
+}
+
+// A JSON array of strings
+fn json_strings(strings: &[impl AsRef<str>]) -> String {
+    let strings: Vec<String> = strings.iter().map(|s| json_string(s.as_ref())).collect();
+    format!("[{}]", strings.join(", "))
+}
+
+// A JSON number, or null when it isn't known
+fn json_number(n: Option<impl ToString>) -> String {
+    n.map_or("null".to_string(), |n| n.to_string())
+}
+
+/// Like [`verify_bin`], printing the parsed cue sheet and the result of every check as a single JSON object for
+/// scripts to read: the disc mode, every bin with its size and tracks, every track with its indexes, byte range
+/// and problems, the notes about the whole disc, what `rcue` reads differently and whether the whole check passed.
+/// Unknown numbers are `null`, and the `rcue` cross-check is `null` when the cue sheet can't be reread.
+pub fn verify_bin_json(cue_path: &str) -> Result<bool, BinmergeError> {
+    let report = verify_report(cue_path)?;
+
+    let mut files = Vec::new();
+    for (i, bin_file) in report.bin_files.iter().enumerate() {
+        let mut tracks = Vec::new();
+        for (track, problems) in bin_file.tracks.iter().zip(&report.problems[i]) {
+            let (start, end) = track.byte_range(track.sector_size);
+            let indexes: Vec<String> = track
+                .iter_indexes()
+                .map(|index| {
+                    format!(
+                        "{{\"id\": {}, \"stamp\": {}, \"file_offset\": {}}}",
+                        index.id,
+                        json_string(&index.stamp),
+                        index.file_offset
+                    )
+                })
+                .collect();
+            tracks.push(format!(
+                "{{\"num\": {}, \"type\": {}, \"sector_size\": {}, \"file_offset\": {}, \"sectors\": {}, \
+                 \"start\": {}, \"end\": {}, \"indexes\": [{}], \"problems\": {}}}",
+                track.num,
+                json_string(&track.track_type),
+                track.sector_size,
+                json_number(track.file_offset),
+                json_number(track.sectors),
+                start,
+                json_number(end),
+                indexes.join(", "),
+                json_strings(problems)
+            ));
+        }
+
+        files.push(format!(
+            "{{\"filename\": {}, \"format\": {}, \"size\": {}, \"tracks_size\": {}, \"tracks\": [\n      {}\n    ]}}",
+            json_string(&bin_file.filename.to_string_lossy()),
+            json_string(&bin_file.format),
+            json_number(bin_file.size),
+            report.tracks_sizes[i],
+            tracks.join(",\n      ")
+        ));
+    }
+    let cross_check = match &report.cross_check {
+        Some(Ok(differences)) => format!("{{\"differences\": {}}}", json_strings(differences)),
+        Some(Err(e)) => format!("{{\"error\": {}}}", json_string(e)),
+        None => "null".to_string(),
+    };
+
+    println!("{{");
+    println!("  \"disc_mode\": {},", json_string(&report.disc_mode.to_string()));
+    println!("  \"files\": [\n    {}\n  ],", files.join(",\n    "));
+    println!("  \"total_sectors\": {},", report.total_sectors);
+    println!("  \"total_length\": {},", json_string(&total_length(&report.bin_files)));
+    println!("  \"notes\": {},", json_strings(&report.notes));
+    println!("  \"cross_check\": {},", cross_check);
+    println!("  \"valid\": {}", report.valid);
+    println!("}}");
+    Ok(report.valid)
+}
@@ -0,0 +1,135 @@
+use std::fs::OpenOptions;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use log::{error, warn};
+
+use crate::cue::get_bin_from_cue;
+use crate::dir::{create_outdir, output_path, sanitize_filename};
+use crate::error::BinmergeError;
+use crate::merge::check_raw_format;
+use crate::split::{copy_track, track_extent, track_filename};
+
+/// Size of the header [`wav_header`] puts in front of the audio samples.
+pub const WAV_HEADER_SIZE: usize = 44;
+
+/// RIFF/WAVE header for `data_len` bytes of CD audio, which is 16-bit little-endian stereo PCM at 44.1 kHz.
+///
+/// Fails when there's too much audio for the 32-bit sizes of a RIFF file, which is far more than a CD holds.
+pub fn wav_header(data_len: u64) -> io::Result<[u8; WAV_HEADER_SIZE]> {
+    let riff_len = u32::try_from(data_len + WAV_HEADER_SIZE as u64 - 8)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Too much audio data for a WAV file"))?;
+    let (channels, sample_rate, bits_per_sample) = (2u16, 44100u32, 16u16);
+    let block_align = channels * bits_per_sample / 8;
+
+    let mut header = [0; WAV_HEADER_SIZE];
+    header[0..4].copy_from_slice(b"RIFF");
+    header[4..8].copy_from_slice(&riff_len.to_le_bytes());
+    header[8..12].copy_from_slice(b"WAVE");
+    header[12..16].copy_from_slice(b"fmt ");
+    header[16..20].copy_from_slice(&16u32.to_le_bytes());
+    header[20..22].copy_from_slice(&1u16.to_le_bytes()); // PCM
+    header[22..24].copy_from_slice(&channels.to_le_bytes());
+    header[24..28].copy_from_slice(&sample_rate.to_le_bytes());
+    header[28..32].copy_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+    header[32..34].copy_from_slice(&block_align.to_le_bytes());
+    header[34..36].copy_from_slice(&bits_per_sample.to_le_bytes());
+    header[36..40].copy_from_slice(b"data");
+    header[40..44].copy_from_slice(&(data_len as u32).to_le_bytes());
+    Ok(header)
+}
+
+/// Writes every `AUDIO` track of the cue sheet at `cue_path` to a WAV file in `outdir`, named after
+/// `track_template` like [`split_bin_named`](crate::split_bin_named) names track bins but with a `.wav` extension.
+///
+/// Data tracks are skipped with a warning. The samples are written as they are in the bin, which for `BINARY`
+/// files is the little-endian order WAV files use. Returns `false` without writing anything more when a WAV file
+/// already exists.
+pub fn export_wav(cue_path: &str, outdir: &Path, track_template: &str) -> Result<bool, BinmergeError> {
+    let bin_files = get_bin_from_cue(cue_path)?;
+    for bin_file in &bin_files {
+        check_raw_format(&bin_file.format, "export")?;
+    }
+    create_outdir(outdir)?;
+    let basename = sanitize_filename(&Path::new(cue_path).file_stem().unwrap_or_default().to_string_lossy());
+
+    for bin_file in &bin_files {
+        if bin_file.format == "MOTOROLA" && bin_file.tracks.iter().any(|track| track.mode == "AUDIO") {
+            warn!("{} is big-endian, its audio tracks will play back as noise", bin_file.filename.display());
+        }
+        for (i, track) in bin_file.tracks.iter().enumerate() {
+            // CDG tracks hold subcode along with the samples, so only plain AUDIO tracks are PCM
+            if track.mode != "AUDIO" {
+                warn!("Skipping track {:02}, a {} track isn't audio", track.num, track.track_type);
+                continue;
+            }
+            let track_name = track_filename(track_template, &basename, track.num);
+            let wav_name = Path::new(&track_name).with_extension("wav");
+            let wav_path = output_path(outdir, &wav_name.to_string_lossy())?;
+            if wav_path.exists() {
+                error!("Target WAV path already exists: {}", wav_path.display());
+                return Ok(false);
+            }
+
+            let (start, end) = track_extent(bin_file, i)?;
+            let mut wav = BufWriter::new(OpenOptions::new().write(true).create_new(true).open(&wav_path)?);
+            wav.write_all(&wav_header(end - start)?)?;
+            copy_track(bin_file, i, &mut wav)?;
+            wav.flush()?;
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::split::DEFAULT_TRACK_TEMPLATE;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn header_of_one_second_of_audio() {
+        // 75 sectors of 2352 bytes, 44100 stereo samples of 16 bits
+        let header = wav_header(75 * 2352).unwrap();
+        let mut expected = Vec::new();
+        expected.extend_from_slice(b"RIFF");
+        expected.extend_from_slice(&[0x34, 0xb1, 0x02, 0x00]); // 176436 bytes after this field
+        expected.extend_from_slice(b"WAVEfmt ");
+        expected.extend_from_slice(&[0x10, 0x00, 0x00, 0x00]); // 16 byte fmt chunk
+        expected.extend_from_slice(&[0x01, 0x00, 0x02, 0x00]); // PCM, 2 channels
+        expected.extend_from_slice(&[0x44, 0xac, 0x00, 0x00]); // 44100 Hz
+        expected.extend_from_slice(&[0x10, 0xb1, 0x02, 0x00]); // 176400 bytes per second
+        expected.extend_from_slice(&[0x04, 0x00, 0x10, 0x00]); // 4 byte frames of 16 bit samples
+        expected.extend_from_slice(b"data");
+        expected.extend_from_slice(&[0x10, 0xb1, 0x02, 0x00]); // 176400 bytes of samples
+        assert_eq!(header.as_slice(), expected.as_slice());
+    }
+
+    #[test]
+    fn header_is_refused_past_the_riff_size_limit() {
+        assert!(wav_header(u32::MAX as u64 - 36).is_ok());
+        assert!(wav_header(u32::MAX as u64 - 35).is_err());
+    }
+
+    #[test]
+    fn only_audio_tracks_are_exported() {
+        let dir = TempDir::new("wav-export");
+        dir.write("game.bin", [vec![1; 2 * 2352], vec![2; 3 * 2352]].concat());
+        let cuesheet = concat!(
+            "FILE \"game.bin\" BINARY\n",
+            "  TRACK 01 MODE1/2352\n",
+            "    INDEX 01 00:00:00\n",
+            "  TRACK 02 AUDIO\n",
+            "    INDEX 01 00:00:02\n",
+        );
+        let cue = dir.write("game.cue", cuesheet);
+        let outdir = dir.path().join("wav");
+        assert!(export_wav(&cue.to_string_lossy(), &outdir, DEFAULT_TRACK_TEMPLATE).unwrap());
+
+        assert!(!outdir.join("game (Track 01).wav").exists());
+        let wav = fs::read(outdir.join("game (Track 02).wav")).unwrap();
+        assert_eq!(wav[..WAV_HEADER_SIZE], wav_header(3 * 2352).unwrap());
+        assert_eq!(wav[WAV_HEADER_SIZE..], [2; 3 * 2352]);
+    }
+}
@@ -0,0 +1,305 @@
+mod common;
+
+use std::fs;
+
+use common::{binmerge, sectors, Fixture, TempDir};
+
+// A data track and an audio track in bins of their own
+fn two_bins(name: &str) -> Fixture {
+    let mut fixture = Fixture::new(name);
+    fixture.file("game (Track 1).bin", &sectors(4, 2352, 1)).track(1, "MODE2/2352").index(1, "00:00:00");
+    fixture.file("game (Track 2).bin", &sectors(3, 2352, 2)).track(2, "AUDIO").index(1, "00:00:00");
+    fixture.write_cue("game.cue");
+    fixture
+}
+
+#[test]
+fn cue_named_without_a_directory_is_read_from_the_current_one() {
+    let fixture = two_bins("bare-cue-name");
+    let output = binmerge(fixture.dir()).args(["merge", "--input", "game.cue", "--outdir", "out"]).output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(fixture.dir().join("out/game.bin").metadata().unwrap().len(), 7 * 2352);
+}
+
+#[test]
+fn merge_larger_than_max_size_is_refused_before_writing() {
+    let fixture = two_bins("max-size");
+    let output = binmerge(fixture.dir())
+        .args(["merge", "--input", "game.cue", "--outdir", "out", "--max-size", "16K"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let refused = "The merged bin would be 16464 bytes, more than the --max-size of 16384 bytes";
+    assert!(stderr.contains(refused), "{}", stderr);
+    assert!(!fixture.dir().join("out").exists());
+
+    let output = binmerge(fixture.dir())
+        .args(["merge", "--input", "game.cue", "--outdir", "out", "--max-size", "17K"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+}
+
+#[test]
+fn exit_code_tells_success_from_failure_and_bad_arguments() {
+    let fixture = two_bins("exit-codes");
+    let merge = |input: &str| binmerge(fixture.dir()).args(["merge", "--input", input, "--outdir", "out"]).output();
+    assert_eq!(merge("game.cue").unwrap().status.code(), Some(0));
+    assert_eq!(merge("missing.cue").unwrap().status.code(), Some(1));
+    // Merging again would overwrite the merged bin without --overwrite
+    assert_eq!(merge("game.cue").unwrap().status.code(), Some(1));
+
+    let status = binmerge(fixture.dir()).args(["merge", "--input", "game.cue"]).output().unwrap().status;
+    assert_eq!(status.code(), Some(2));
+    let status = binmerge(fixture.dir()).args(["shuffle", "--input", "game.cue"]).output().unwrap().status;
+    assert_eq!(status.code(), Some(2));
+}
+
+#[test]
+fn verify_json_prints_every_track_and_the_result() {
+    let fixture = two_bins("verify-json");
+    let output = binmerge(fixture.dir()).args(["verify", "--input", "game.cue", "--json"]).output().unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("{\n") && stdout.ends_with("}\n"), "{}", stdout);
+    for expected in [
+        "\"format\": \"BINARY\", \"size\": 9408, \"tracks_size\": 9408",
+        "\"num\": 1, \"type\": \"MODE2/2352\", \"sector_size\": 2352, \"file_offset\": 0, \"sectors\": 4, \
+         \"start\": 0, \"end\": 9408, \"indexes\": [{\"id\": 1, \"stamp\": \"00:00:00\", \"file_offset\": 0}], \
+         \"problems\": []",
+        "\"num\": 2, \"type\": \"AUDIO\", \"sector_size\": 2352, \"file_offset\": 0, \"sectors\": 3",
+        "\"total_sectors\": 7,",
+        "\"notes\": [],",
+        "\"cross_check\": {\"differences\": []},",
+        "\"valid\": true\n",
+    ] {
+        assert!(stdout.contains(expected), "{} not in {}", expected, stdout);
+    }
+
+    // A bin cut short partway through a sector fails the check, with its tracks still listed
+    fs::write(fixture.dir().join("game (Track 2).bin"), &sectors(3, 2352, 2)[..2 * 2352 + 100]).unwrap();
+    let output = binmerge(fixture.dir()).args(["verify", "--input", "game.cue", "--json"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("\"num\": 2, \"type\": \"AUDIO\"") && stdout.contains("\"valid\": false\n"), "{}", stdout);
+}
+
+// The JSON report says what the text report does, from the same checks
+#[test]
+fn verify_json_notes_what_the_text_report_notes() {
+    let mut fixture = Fixture::new("verify-json-notes");
+    fixture.file("game.bin", &sectors(100, 2352, 1));
+    for num in 1..=100 {
+        fixture.track(num, "AUDIO").index(1, &format!("00:{:02}:{:02}", (num - 1) / 75, (num - 1) % 75));
+    }
+    fixture.write_cue("game.cue");
+
+    let output = binmerge(fixture.dir()).args(["verify", "--input", "game.cue"]).output().unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout).unwrap().contains("\nMore than the 99 tracks a disc can hold\n"));
+    let output = binmerge(fixture.dir()).args(["verify", "--input", "game.cue", "--json"]).output().unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("\"notes\": [\"More than the 99 tracks a disc can hold\"],"), "{}", stdout);
+
+    // rcue can only reread a cue sheet from a file
+    let cuesheet = fs::read(fixture.dir().join("game.cue")).unwrap();
+    let mut child = binmerge(fixture.dir())
+        .args(["verify", "--input", "-", "--json"])
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    std::io::Write::write_all(&mut child.stdin.take().unwrap(), &cuesheet).unwrap();
+    let stdout = String::from_utf8(child.wait_with_output().unwrap().stdout).unwrap();
+    assert!(stdout.contains("\"cross_check\": null,"), "{}", stdout);
+}
+
+#[test]
+fn list_csv_quotes_filenames_with_commas() {
+    let mut fixture = Fixture::new("list-csv");
+    fixture.file("Game, The (Track 1).bin", &sectors(150, 2352, 1)).track(1, "MODE1/2352").index(1, "00:00:00");
+    fixture.file("Game, The (Track 2).bin", &sectors(300, 2352, 2)).track(2, "AUDIO");
+    fixture.index(0, "00:00:00").index(1, "00:02:00");
+    fixture.write_cue("game.cue");
+
+    let output = binmerge(fixture.dir()).args(["list", "--input", "game.cue", "--format", "csv"]).output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        concat!(
+            "track,type,start,length,file\n",
+            "01,MODE1/2352,00:00:00,00:02:00,\"Game, The (Track 1).bin\"\n",
+            "02,AUDIO,00:02:00,00:04:00,\"Game, The (Track 2).bin\"\n",
+        )
+    );
+}
+
+#[test]
+fn flat_batch_numbers_cue_sheets_with_the_same_name() {
+    let dir = TempDir::new("batch-flat");
+    // The same name in another case collides too, on the FAT drives flat layouts are made for
+    for (subdir, name, sector_count) in [("a", "game", 2), ("b", "GAME", 3), ("c", "Other", 4)] {
+        let indir = dir.path().join("in").join(subdir);
+        fs::create_dir_all(&indir).unwrap();
+        let mut cuesheet = String::new();
+        for num in 1..=2 {
+            let bin = format!("{} (Track {}).bin", name, num);
+            fs::write(indir.join(&bin), sectors(sector_count, 2352, num)).unwrap();
+            cuesheet += &format!("FILE \"{}\" BINARY\n  TRACK {:02} AUDIO\n    INDEX 01 00:00:00\n", bin, num);
+        }
+        fs::write(indir.join(format!("{}.cue", name)), cuesheet).unwrap();
+    }
+
+    let output = binmerge(dir.path()).args(["batch", "--input", "in", "--outdir", "out", "--flat"]).output().unwrap();
+    assert!(output.status.success());
+    let mut merged: Vec<_> = fs::read_dir(dir.path().join("out"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    merged.sort();
+    assert_eq!(merged, ["GAME (2).bin", "GAME (2).cue", "Other.bin", "Other.cue", "game.bin", "game.cue"]);
+    assert_eq!(fs::metadata(dir.path().join("out/game.bin")).unwrap().len(), 4 * 2352);
+    assert_eq!(fs::metadata(dir.path().join("out/GAME (2).bin")).unwrap().len(), 6 * 2352);
+    let cuesheet = fs::read_to_string(dir.path().join("out/GAME (2).cue")).unwrap();
+    assert!(cuesheet.starts_with("FILE \"GAME (2).bin\" BINARY"), "{}", cuesheet);
+}
+
+// One byte at a time, the merge is still copying the bin when it's interrupted
+#[cfg(unix)]
+#[test]
+fn interrupted_merge_removes_its_partial_bin() {
+    use std::process::Stdio;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    let mut fixture = Fixture::new("interrupt");
+    fixture.file("game (Track 1).bin", &sectors(4, 2352, 1)).track(1, "MODE1/2352").index(1, "00:00:00");
+    fixture.file("game (Track 2).bin", &vec![2; 16 << 20]).track(2, "AUDIO").index(1, "00:00:00");
+    fixture.write_cue("game.cue");
+    // Left by an earlier merge that was killed outright, which the interrupted one has to leave alone
+    let outdir = fixture.dir().join("out");
+    fs::create_dir(&outdir).unwrap();
+    fs::write(outdir.join("game.bin.1.part"), "not ours").unwrap();
+
+    let mut child = binmerge(fixture.dir())
+        .args(["merge", "--input", "game.cue", "--outdir", "out", "--buffer-size", "1"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let partial = outdir.join(format!("game.bin.{}.part", child.id()));
+    let started = Instant::now();
+    while !partial.exists() {
+        assert!(started.elapsed() < Duration::from_secs(10), "the merge never started");
+        thread::sleep(Duration::from_millis(10));
+    }
+    let status = std::process::Command::new("kill").args(["-INT", &child.id().to_string()]).status().unwrap();
+    assert!(status.success());
+
+    assert_eq!(child.wait().unwrap().code(), Some(130));
+    assert!(!partial.exists());
+    assert!(!outdir.join("game.bin").exists());
+    assert_eq!(fs::read_to_string(outdir.join("game.bin.1.part")).unwrap(), "not ours");
+}
+
+// Data in an .img and audio in a .bin, as some dumping tools lay them out
+#[test]
+fn img_data_is_merged_under_the_extension_asked_for() {
+    let mut fixture = Fixture::new("img-ext");
+    fixture.file("game.img", &sectors(4, 2048, 1)).track(1, "MODE1/2048").index(1, "00:00:00");
+    fixture.file("game (Track 2).bin", &sectors(3, 2352, 2)).track(2, "AUDIO").index(1, "00:00:00");
+    fixture.write_cue("game.cue");
+
+    let output = binmerge(fixture.dir())
+        .args(["merge", "--input", "game.cue", "--outdir", "out", "--ext", ".img"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let merged = fs::read(fixture.dir().join("out/game.img")).unwrap();
+    assert_eq!(merged, [sectors(4, 2048, 1), sectors(3, 2352, 2)].concat());
+    assert!(!fixture.dir().join("out/game.bin").exists());
+    let cuesheet = fs::read_to_string(fixture.dir().join("out/game.cue")).unwrap();
+    assert!(cuesheet.starts_with("FILE \"game.img\" BINARY\n  TRACK 01 MODE1/2048\n"), "{}", cuesheet);
+
+    // The merged bin can't take the cue sheet's place
+    for ext in ["cue", "", "."] {
+        let output = binmerge(fixture.dir())
+            .args(["merge", "--input", "game.cue", "--outdir", "other", "--ext", ext])
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(1), "{}", ext);
+        assert!(String::from_utf8_lossy(&output.stderr).contains("Invalid extension for the merged bin"));
+    }
+}
+
+#[test]
+fn input_list_bins_are_concatenated_in_the_order_listed() {
+    let dir = TempDir::new("concat-input-list");
+    fs::create_dir(dir.path().join("fragments")).unwrap();
+    fs::write(dir.path().join("fragments/b.bin"), sectors(2, 2352, 2)).unwrap();
+    fs::write(dir.path().join("fragments/a.bin"), sectors(3, 2352, 1)).unwrap();
+    fs::write(dir.path().join("fragments/list.txt"), "b.bin\n\na.bin\n").unwrap();
+
+    let output = binmerge(dir.path())
+        .args(["concat", "--input-list", "fragments/list.txt", "--outdir", "out", "--name", "disc"])
+        .args(["--cue", "mode2/2352"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let merged = fs::read(dir.path().join("out/disc.bin")).unwrap();
+    assert_eq!(merged, [sectors(2, 2352, 2), sectors(3, 2352, 1)].concat());
+    assert_eq!(
+        fs::read_to_string(dir.path().join("out/disc.cue")).unwrap(),
+        "FILE \"disc.bin\" BINARY\n  TRACK 01 MODE2/2352\n    INDEX 01 00:00:00\n"
+    );
+
+    // Nothing is written when a listed bin doesn't exist
+    fs::write(dir.path().join("fragments/list.txt"), "b.bin\nmissing.bin\n").unwrap();
+    let output = binmerge(dir.path())
+        .args(["concat", "--input-list", "fragments/list.txt", "--outdir", "other", "--name", "disc"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("line 2 of fragments/list.txt"));
+    assert!(!dir.path().join("other").exists());
+}
+
+#[test]
+fn appended_tracks_are_numbered_on_from_the_merged_image() {
+    let fixture = two_bins("append-first-track");
+    let merge = |args: &[&str]| {
+        binmerge(fixture.dir()).args(["merge", "--outdir", "out", "--name", "game"]).args(args).output().unwrap()
+    };
+    let output = merge(&["--input", "game.cue"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    fs::write(fixture.dir().join("bonus.bin"), sectors(2, 2352, 3)).unwrap();
+    let bonus_cue = "FILE \"bonus.bin\" BINARY\n  TRACK 01 AUDIO\n    INDEX 01 00:00:00\n";
+    fs::write(fixture.dir().join("bonus.cue"), bonus_cue).unwrap();
+
+    let output = merge(&["--input", "bonus.cue", "--append"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    // Track 3 is among the merged image's tracks now, so numbering from it again would repeat it
+    let output = merge(&["--input", "bonus.cue", "--append", "--first-track", "3"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--first-track 3 doesn't come after track 3"));
+    let output = merge(&["--input", "bonus.cue", "--append", "--first-track", "10"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let merged = fs::read(fixture.dir().join("out/game.bin")).unwrap();
+    let bonus = sectors(2, 2352, 3);
+    assert_eq!(merged, [sectors(4, 2352, 1), sectors(3, 2352, 2), bonus.clone(), bonus].concat());
+    assert_eq!(
+        fs::read_to_string(fixture.dir().join("out/game.cue")).unwrap(),
+        concat!(
+            "FILE \"game.bin\" BINARY\n",
+            "  TRACK 01 MODE2/2352\n    INDEX 01 00:00:00\n",
+            "  TRACK 02 AUDIO\n    INDEX 01 00:00:04\n",
+            "  TRACK 03 AUDIO\n    INDEX 01 00:00:07\n",
+            "  TRACK 10 AUDIO\n    INDEX 01 00:00:09\n",
+        )
+    );
+    // A starting track means nothing without an image to append to
+    assert_eq!(merge(&["--input", "bonus.cue", "--first-track", "4"]).status.code(), Some(2));
+}
@@ -0,0 +1,91 @@
+// Fixtures shared by the integration tests, each of which only uses some of them
+#![allow(dead_code)]
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+
+/// A scratch directory, removed along with everything in it once the test is done.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Named after the test so that tests running at the same time each get their own.
+    pub fn new(name: &str) -> TempDir {
+        let path = env::temp_dir().join(format!("binmerge-test-{}-{}", process::id(), name));
+        if path.exists() {
+            fs::remove_dir_all(&path).unwrap();
+        }
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// `count` sectors of `sector_size` bytes that can't be mistaken for those of another track or for each other: every
+/// byte is the `tag` of the track, the number of its sector and its position in the sector mixed together.
+pub fn sectors(count: usize, sector_size: usize, tag: u8) -> Vec<u8> {
+    (0..count * sector_size)
+        .map(|i| tag ^ ((i / sector_size) as u8).wrapping_mul(31) ^ (i % sector_size) as u8)
+        .collect()
+}
+
+/// A multi-bin cue sheet and its bins, written to a scratch directory as the cue sheet is built.
+pub struct Fixture {
+    dir: TempDir,
+    cuesheet: String,
+}
+
+impl Fixture {
+    pub fn new(name: &str) -> Fixture {
+        Fixture { dir: TempDir::new(name), cuesheet: String::new() }
+    }
+
+    pub fn dir(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// Writes `data` to the bin `name` and starts a `FILE` referencing it.
+    pub fn file(&mut self, name: &str, data: &[u8]) -> &mut Fixture {
+        fs::write(self.dir().join(name), data).unwrap();
+        self.line(&format!("FILE \"{}\" BINARY", name))
+    }
+
+    pub fn track(&mut self, num: u32, track_type: &str) -> &mut Fixture {
+        self.line(&format!("  TRACK {:02} {}", num, track_type))
+    }
+
+    pub fn index(&mut self, id: u32, stamp: &str) -> &mut Fixture {
+        self.line(&format!("    INDEX {:02} {}", id, stamp))
+    }
+
+    /// Adds any other line to the cue sheet as is.
+    pub fn line(&mut self, line: &str) -> &mut Fixture {
+        self.cuesheet += line;
+        self.cuesheet += "\n";
+        self
+    }
+
+    /// Writes the cue sheet built so far as `name` and returns its path.
+    pub fn write_cue(&self, name: &str) -> PathBuf {
+        let cue = self.dir().join(name);
+        fs::write(&cue, &self.cuesheet).unwrap();
+        cue
+    }
+}
+
+/// The binmerge command line tool, run from `dir`.
+pub fn binmerge(dir: &Path) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_binmerge"));
+    command.current_dir(dir);
+    command
+}
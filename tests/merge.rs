@@ -0,0 +1,123 @@
+mod common;
+
+use std::fs;
+
+use binmerge_rs::{get_bin_from_cue, render_merged_cue, total_sectors};
+
+use common::{binmerge, sectors, Fixture};
+
+// MM:SS:FF of a sector count, written out here rather than with the crate's own conversion
+fn stamp(sectors: u32) -> String {
+    format!("{:02}:{:02}:{:02}", sectors / 4500, sectors / 75 % 60, sectors % 75)
+}
+
+#[test]
+fn data_track_and_twelve_audio_tracks_are_laid_out_one_after_the_other() {
+    // A PSX rip: the data track in one bin, then every audio track in its own bin, starting with a 2 second pregap
+    let mut fixture = Fixture::new("merge-psx-layout");
+    fixture.file("game (Track 01).bin", &sectors(300, 2352, 1)).track(1, "MODE2/2352").index(1, "00:00:00");
+    let mut audio_sectors = Vec::new();
+    for num in 2..=13 {
+        let count = 150 + 10 * num as usize;
+        audio_sectors.push(count as u32);
+        fixture.file(&format!("game (Track {:02}).bin", num), &sectors(count, 2352, num as u8));
+        fixture.track(num, "AUDIO").index(0, "00:00:00").index(1, "00:02:00");
+    }
+    let cue = fixture.write_cue("game.cue");
+
+    let bin_files = get_bin_from_cue(&cue.to_string_lossy()).unwrap();
+    let cuesheet = render_merged_cue(&bin_files, "game.bin").unwrap();
+
+    let mut expected = String::from("FILE \"game.bin\" BINARY\n  TRACK 01 MODE2/2352\n    INDEX 01 00:00:00\n");
+    let mut start = 300;
+    for (num, count) in (2..).zip(audio_sectors) {
+        expected += &format!("  TRACK {:02} AUDIO\n", num);
+        expected += &format!("    INDEX 00 {}\n    INDEX 01 {}\n", stamp(start), stamp(start + 150));
+        start += count;
+    }
+    assert!(cuesheet.contains("  TRACK 02 AUDIO\n    INDEX 00 00:04:00\n    INDEX 01 00:06:00\n"));
+    assert_eq!(cuesheet, expected);
+    assert_eq!(total_sectors(&bin_files), start);
+}
+
+// An audio CD: track 2 has its pregap stored at the start of its bin as an INDEX 00, track 3 has a PREGAP that isn't
+// stored anywhere. Merged with --pregap-mode, returns the merged cue sheet and the size of the merged bin in sectors
+fn merge_audio_cd(name: &str, pregap_mode: &str) -> (String, u64) {
+    let mut fixture = Fixture::new(name);
+    fixture.file("cd (Track 1).bin", &sectors(300, 2352, 1)).track(1, "AUDIO").index(1, "00:00:00");
+    fixture.file("cd (Track 2).bin", &sectors(400, 2352, 2)).track(2, "AUDIO").index(0, "00:00:00");
+    fixture.index(1, "00:02:13");
+    fixture.file("cd (Track 3).bin", &sectors(200, 2352, 3)).track(3, "AUDIO").line("    PREGAP 00:02:00");
+    fixture.index(1, "00:00:00");
+    fixture.write_cue("cd.cue");
+
+    let output = binmerge(fixture.dir())
+        .args(["merge", "--input", "cd.cue", "--outdir", "out", "--pregap-mode", pregap_mode])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let cuesheet = fs::read_to_string(fixture.dir().join("out/cd.cue")).unwrap();
+    let size = fs::metadata(fixture.dir().join("out/cd.bin")).unwrap().len();
+    assert_eq!(size % 2352, 0);
+    (cuesheet, size / 2352)
+}
+
+#[test]
+fn audio_cd_pregaps_are_preserved() {
+    let (cuesheet, sectors) = merge_audio_cd("merge-pregap-preserve", "preserve");
+    assert_eq!(
+        cuesheet,
+        concat!(
+            "FILE \"cd.bin\" BINARY\n",
+            "  TRACK 01 AUDIO\n",
+            "    INDEX 01 00:00:00\n",
+            "  TRACK 02 AUDIO\n",
+            "    INDEX 00 00:04:00\n",
+            "    INDEX 01 00:06:13\n",
+            "  TRACK 03 AUDIO\n",
+            "    PREGAP 00:02:00\n",
+            "    INDEX 01 00:09:25\n",
+        )
+    );
+    assert_eq!(sectors, 900);
+}
+
+#[test]
+fn audio_cd_pregaps_are_embedded() {
+    let (cuesheet, sectors) = merge_audio_cd("merge-pregap-embed", "embed");
+    assert_eq!(
+        cuesheet,
+        concat!(
+            "FILE \"cd.bin\" BINARY\n",
+            "  TRACK 01 AUDIO\n",
+            "    INDEX 01 00:00:00\n",
+            "  TRACK 02 AUDIO\n",
+            "    INDEX 00 00:04:00\n",
+            "    INDEX 01 00:06:13\n",
+            "  TRACK 03 AUDIO\n",
+            "    INDEX 00 00:09:25\n",
+            "    INDEX 01 00:11:25\n",
+        )
+    );
+    assert_eq!(sectors, 1050);
+}
+
+#[test]
+fn audio_cd_pregaps_are_stripped() {
+    let (cuesheet, sectors) = merge_audio_cd("merge-pregap-strip", "strip");
+    assert_eq!(
+        cuesheet,
+        concat!(
+            "FILE \"cd.bin\" BINARY\n",
+            "  TRACK 01 AUDIO\n",
+            "    INDEX 01 00:00:00\n",
+            "  TRACK 02 AUDIO\n",
+            "    PREGAP 00:02:13\n",
+            "    INDEX 01 00:04:00\n",
+            "  TRACK 03 AUDIO\n",
+            "    PREGAP 00:02:00\n",
+            "    INDEX 01 00:07:12\n",
+        )
+    );
+    assert_eq!(sectors, 737);
+}
@@ -0,0 +1,82 @@
+mod common;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use binmerge_rs::{
+    get_bin_from_cue, merge_files, split_bin, write_merged_cue, BinmergeError, CueParseError, LineEnding,
+    DEFAULT_BUFFER_SIZE,
+};
+
+use common::{sectors, Fixture};
+
+// Merges the bins of cue into game.bin and game.cue in outdir, returning the merged cue sheet
+fn merge(cue: &Path, outdir: &Path) -> PathBuf {
+    let bin_files = get_bin_from_cue(&cue.to_string_lossy()).unwrap();
+    fs::create_dir(outdir).unwrap();
+    let bins = bin_files.iter().map(|bin_file| bin_file.filename.as_path()).collect();
+    merge_files(&outdir.join("game.bin"), bins, false, DEFAULT_BUFFER_SIZE).unwrap();
+    let merged_cue = outdir.join("game.cue");
+    write_merged_cue(&bin_files, &merged_cue, "game.bin", false, LineEnding::Lf).unwrap();
+    merged_cue
+}
+
+// Merges the fixture's game.cue, splits the merged bin back out and merges the split bins again. The split bins and
+// cue sheet have to be the fixture's own, and the second merge the same as the first
+fn assert_round_trip(fixture: &Fixture, bins: &[&str]) {
+    let dir = fixture.dir();
+    let merged_cue = merge(&dir.join("game.cue"), &dir.join("merged"));
+    assert!(split_bin(&merged_cue.to_string_lossy(), &dir.join("split")).unwrap());
+    for bin in bins {
+        assert_eq!(fs::read(dir.join("split").join(bin)).unwrap(), fs::read(dir.join(bin)).unwrap(), "{}", bin);
+    }
+    let split_cue = dir.join("split/game.cue");
+    assert_eq!(fs::read_to_string(&split_cue).unwrap(), fs::read_to_string(dir.join("game.cue")).unwrap());
+
+    let remerged_cue = merge(&split_cue, &dir.join("remerged"));
+    assert_eq!(fs::read(dir.join("remerged/game.bin")).unwrap(), fs::read(dir.join("merged/game.bin")).unwrap());
+    assert_eq!(fs::read_to_string(remerged_cue).unwrap(), fs::read_to_string(merged_cue).unwrap());
+}
+
+#[test]
+fn merge_split_and_merge_again_gives_the_same_bins() {
+    let mut fixture = Fixture::new("roundtrip-indexes");
+    fixture.file("game (Track 01).bin", &sectors(20, 2352, 1)).track(1, "MODE2/2352").index(1, "00:00:00");
+    fixture.file("game (Track 02).bin", &sectors(150, 2352, 2)).track(2, "AUDIO").index(0, "00:00:00");
+    fixture.index(1, "00:00:30").index(2, "00:01:00");
+    fixture.file("game (Track 03).bin", &sectors(40, 2352, 3)).track(3, "AUDIO").index(1, "00:00:00");
+    fixture.write_cue("game.cue");
+
+    assert_round_trip(&fixture, &["game (Track 01).bin", "game (Track 02).bin", "game (Track 03).bin"]);
+    // Every index of track 2 moved by the 20 sectors of track 1
+    let merged = fs::read_to_string(fixture.dir().join("merged/game.cue")).unwrap();
+    assert!(merged.contains("    INDEX 00 00:00:20\n    INDEX 01 00:00:50\n    INDEX 02 00:01:20\n"));
+}
+
+#[test]
+fn mode1_2048_tracks_round_trip() {
+    let mut fixture = Fixture::new("roundtrip-mode1");
+    fixture.file("game (Track 01).bin", &sectors(30, 2048, 1)).track(1, "MODE1/2048").index(1, "00:00:00");
+    fixture.file("game (Track 02).bin", &sectors(12, 2048, 2)).track(2, "MODE1/2048").index(1, "00:00:00");
+    fixture.write_cue("game.cue");
+
+    assert_round_trip(&fixture, &["game (Track 01).bin", "game (Track 02).bin"]);
+    assert_eq!(fs::metadata(fixture.dir().join("merged/game.bin")).unwrap().len(), 42 * 2048);
+}
+
+#[test]
+fn missing_bin_is_reported() {
+    let mut fixture = Fixture::new("roundtrip-missing");
+    fixture.file("game (Track 01).bin", &sectors(20, 2352, 1)).track(1, "MODE2/2352").index(1, "00:00:00");
+    fixture.file("game (Track 02).bin", &sectors(20, 2352, 2)).track(2, "AUDIO").index(1, "00:00:00");
+    let cue = fixture.write_cue("game.cue");
+    fs::remove_file(fixture.dir().join("game (Track 02).bin")).unwrap();
+
+    match get_bin_from_cue(&cue.to_string_lossy()) {
+        Err(BinmergeError::Parse(CueParseError::MissingBinFiles(missing))) => {
+            assert_eq!(missing.len(), 1);
+            assert!(missing[0].0.ends_with("game (Track 02).bin"));
+        }
+        _ => panic!("a missing bin wasn't reported"),
+    }
+}